@@ -1,3 +1,4 @@
+use crate::png::{CompressionStrategy, FilterStrategy, Placement};
 use clap::Parser;
 
 #[derive(Parser)]
@@ -8,26 +9,198 @@ pub enum PngME {
         file: String,
         chunk_type: String,
         message: Vec<String>,
+        /// Hide the contents of this file rather than a message.
+        #[clap(long = "file", conflicts_with_all = &["message", "stdin"])]
+        input: Option<String>,
+        /// Hide whatever is read from standard input.
+        #[clap(long, conflicts_with = "message")]
+        stdin: bool,
         #[clap(short, long)]
         unchecked: bool,
+        /// One of before-iend, before-idat, after-ihdr or a chunk index.
+        #[clap(short, long, default_value = "before-iend")]
+        at: Placement,
+        /// Which message of this type to replace, counting from zero.
+        #[clap(short = 'n', long, default_value = "0")]
+        index: usize,
+        /// Add another message of this type rather than replacing one.
+        #[clap(long, conflicts_with = "index")]
+        add: bool,
+        /// Replace every chunk of this type with the message split into
+        /// chunks of at most this many bytes, which must be more than 13
+        /// to leave room for each part's header.
+        #[clap(long, conflicts_with_all = &["index", "add"])]
+        split: Option<usize>,
+        /// Encrypt the message to this public key, or the key in this file.
+        /// Can be given more than once.
+        #[clap(
+            short,
+            long = "recipient",
+            conflicts_with_all = &["encrypt", "key-env", "key-file"]
+        )]
+        recipients: Vec<String>,
+        /// Encrypt the message with a passphrase, asked for unless given by
+        /// --key-env or --key-file.
+        #[clap(short, long)]
+        encrypt: bool,
+        /// Read the passphrase from this environment variable.
+        #[clap(long, conflicts_with = "key-file")]
+        key_env: Option<String>,
+        /// Read the passphrase from the first line of this file.
+        #[clap(long)]
+        key_file: Option<String>,
     },
     Decode {
         file: String,
         chunk_type: String,
+        /// Which message of this type to decode, counting from zero.
+        #[clap(short = 'n', long, default_value = "0")]
+        index: usize,
+        /// Decode every message of this type, one per line. Binary data
+        /// can't be told apart that way, so it can't be written as it is.
+        #[clap(long, conflicts_with_all = &["index", "out", "raw"])]
+        all: bool,
+        /// Write the data to this file as it is.
+        #[clap(short, long)]
+        out: Option<String>,
+        /// Write the data to standard output as it is, even if it isn't
+        /// text.
+        #[clap(short, long, conflicts_with = "out")]
+        raw: bool,
+        /// Read the passphrase for an encrypted message from this environment variable.
+        #[clap(long, conflicts_with = "key-file")]
+        key_env: Option<String>,
+        /// Read the passphrase for an encrypted message from the first line of this file.
+        #[clap(long)]
+        key_file: Option<String>,
+        /// Decrypt a message encrypted to public keys with the private key
+        /// in this file.
+        #[clap(short, long)]
+        identity: Option<String>,
     },
     Remove {
         file: String,
         chunk_type: String,
         #[clap(short, long)]
         unchecked: bool,
+        /// Which chunk of this type to remove, counting from zero, or the
+        /// first if not given.
+        #[clap(short = 'n', long)]
+        index: Option<usize>,
+        /// Remove every chunk of this type.
+        #[clap(long, conflicts_with = "index")]
+        all: bool,
     },
     Print {
         file: String,
+        /// Only list chunks of this type, numbered among themselves.
+        #[clap(short = 't', long = "type")]
+        chunk_type: Option<String>,
+        /// Skip over corrupt chunks instead of stopping at the first one.
+        #[clap(short, long)]
+        lenient: bool,
     },
     Scrub {
         file: String,
     },
-    Generate,
+    /// Check a PNG against the specification, listing every problem.
+    Validate {
+        file: String,
+    },
+    /// Losslessly recompress a PNG, writing it only if it gets smaller.
+    Optimize {
+        file: String,
+        /// Write here instead of replacing the file.
+        #[clap(short, long)]
+        output: Option<String>,
+        /// Remove ancillary chunks other than the color space.
+        #[clap(short, long)]
+        strip: bool,
+    },
+    /// Salvage the readable chunks of a damaged PNG into a new file.
+    Repair {
+        file: String,
+        output: String,
+    },
+    /// List the frames of an animated PNG.
+    Frames {
+        file: String,
+    },
+    /// Write each frame of an animated PNG to `<prefix><index>.png`.
+    Extract {
+        file: String,
+        prefix: String,
+        /// Write each frame's own region rather than the whole canvas.
+        #[clap(short, long)]
+        raw: bool,
+    },
+    /// Build an animated PNG from still images of the same size.
+    Assemble {
+        output: String,
+        #[clap(required = true)]
+        frames: Vec<String>,
+        /// Milliseconds to show each frame for.
+        #[clap(short, long, default_value = "100")]
+        delay: u16,
+        /// Times to play the animation, or 0 to loop forever.
+        #[clap(short, long, default_value = "0")]
+        plays: u32,
+    },
+    /// Write a new keypair to `<name>.key` and `<name>.pub`, for encrypting
+    /// messages with --recipient and decrypting them with --identity.
+    Keygen {
+        name: String,
+        /// Make an Ed25519 keypair for signing rather than one for
+        /// encrypting.
+        #[clap(short, long)]
+        sign: bool,
+    },
+    /// Sign the messages of a chunk type, adding a siGN chunk which says
+    /// who signed and lets later changes be caught.
+    Sign {
+        file: String,
+        chunk_type: String,
+        /// The private signing key file, made by `keygen --sign`.
+        #[clap(short, long)]
+        key: String,
+        /// Sign the image header and data as well.
+        #[clap(short, long)]
+        pixels: bool,
+    },
+    /// Check the signatures in a PNG, listing who signed what and whether
+    /// anything has changed since.
+    Verify {
+        file: String,
+        /// Also require a valid signature by this public key, or the key in
+        /// this file.
+        #[clap(short, long)]
+        signer: Option<String>,
+    },
+    Generate {
+        #[clap(short, long, default_value = "min-sum")]
+        filter: FilterStrategy,
+        #[clap(short, long)]
+        interlace: bool,
+        /// Write RGBA rather than the smallest lossless color type and bit
+        /// depth.
+        #[clap(long)]
+        no_reduce: bool,
+        /// zlib compression level, 0 to 9.
+        #[clap(short, long, default_value = "6")]
+        level: u8,
+        /// One of default, filtered, huffman-only or rle.
+        #[clap(short, long, default_value = "default")]
+        strategy: CompressionStrategy,
+        /// Base 2 logarithm of the zlib window, 8 to 15.
+        #[clap(short, long, default_value = "15")]
+        window: u8,
+        /// Largest IDAT chunk in bytes.
+        #[clap(long, default_value = "16384")]
+        idat_size: usize,
+        /// Threads to compress with, or 0 for one per core.
+        #[clap(short, long, default_value = "1")]
+        threads: usize,
+    },
 }
 
 impl PngME {
@@ -1,48 +1,418 @@
 use crate::{
     col, convert,
     err::*,
+    fs,
     img::Img,
-    png::{Chunk, Png},
+    png::{
+        self,
+        crypt::{self, Identity, Recipient},
+        Animation, AsChunkRef, Chunk, ChunkReader, ChunkType, ColorSpace, EncodeOptions, Frame,
+        Ihdr, Message, Placement, Png, Signer, Verification, Verifier,
+    },
     Color, Quad,
 };
+use std::{
+    io::{self, Read, Write},
+    str::FromStr,
+};
 
-pub fn encode(filename: &str, chunk_type: &str, message: &str) -> PngRes {
-    Png::load(filename)?
-        .encode(chunk_type, message)?
-        .save(filename)
+/// How a message is locked before it is hidden.
+pub enum Lock<'a> {
+    None,
+    /// With a passphrase from the environment variable `env`, the first
+    /// line of `file`, or else typed at the terminal.
+    Passphrase {
+        env: Option<&'a str>,
+        file: Option<&'a str>,
+    },
+    /// To public keys, each given as hex or the name of a file holding it.
+    Recipients(&'a [String]),
 }
 
-pub fn encode_unchecked(filename: &str, chunk_type: &str, message: &str) -> PngRes {
-    Png::load(filename)?
-        .encode_unchecked(chunk_type, message)?
-        .save(filename)
+impl Lock<'_> {
+    fn seal(&self, message: &[u8]) -> PngRes<Message> {
+        Ok(match *self {
+            Lock::None => Message::from(message),
+            Lock::Passphrase { env, file } => {
+                Message::Locked(crypt::encrypt(message, &passphrase(env, file, true)?)?)
+            }
+            Lock::Recipients(keys) => {
+                Message::Locked(crypt::encrypt_to(message, &recipients(keys)?)?)
+            }
+        })
+    }
 }
 
-pub fn decode(filename: &str, chunk_type: &str) -> PngRes<String> {
-    Png::load(filename)?.decode(chunk_type)
+/// Replaces the `index`th message of the type, or adds another if there
+/// is no index. With a `split` size, the message replaces every chunk of
+/// the type instead, split into parts. The message is locked first.
+pub fn encode(
+    filename: &str,
+    chunk_type: &str,
+    message: &[u8],
+    lock: Lock,
+    at: Placement,
+    index: Option<usize>,
+    split: Option<usize>,
+) -> PngRes {
+    let mut png = Png::load(filename)?;
+    let index = index.unwrap_or_else(|| png.chunks_by_type(chunk_type).len());
+    let message = lock.seal(message)?;
+    let png = png.set_placement(at);
+
+    match split {
+        Some(size) => png.encode_split(chunk_type, message, size)?,
+        None => png.encode_nth(chunk_type, message, index)?,
+    }
+    .save(filename)
 }
 
-pub fn remove(filename: &str, chunk_type: &str) -> PngRes {
-    Png::load(filename)?.discard(chunk_type)?.save(filename)
+pub fn encode_unchecked(
+    filename: &str,
+    chunk_type: &str,
+    message: &[u8],
+    lock: Lock,
+    at: Placement,
+    index: Option<usize>,
+    split: Option<usize>,
+) -> PngRes {
+    let mut png = Png::load(filename)?;
+    let index = index.unwrap_or_else(|| png.chunks_by_type(chunk_type).len());
+    let message = lock.seal(message)?;
+    let png = png.set_placement(at);
+
+    match split {
+        Some(size) => png.encode_split_unchecked(chunk_type, message, size)?,
+        None => png.encode_nth_unchecked(chunk_type, message, index)?,
+    }
+    .save(filename)
 }
 
-pub fn remove_unchecked(filename: &str, chunk_type: &str) -> PngRes {
+/// The data to hide: the contents of `file`, standard input or the words
+/// of the message joined by spaces.
+pub fn message(words: &[String], file: Option<&str>, stdin: bool) -> PngRes<Vec<u8>> {
+    if let Some(file) = file {
+        return fs::read(file);
+    }
+
+    if stdin {
+        let mut bytes = Vec::new();
+        io::stdin()
+            .read_to_end(&mut bytes)
+            .map_err(|_| PngErr::ReadError)?;
+        return Ok(bytes);
+    }
+
+    Ok(words.join(" ").into_bytes())
+}
+
+/// Decodes the `index`th message of the type, or all of them one per line
+/// if there is no index. Messages encrypted to public keys are decrypted
+/// with the private key in `identity`, and others with a passphrase from
+/// `key_env` or `key_file`, or else one asked for.
+pub fn decode(
+    filename: &str,
+    chunk_type: &str,
+    index: Option<usize>,
+    key_env: Option<&str>,
+    key_file: Option<&str>,
+    identity: Option<&str>,
+) -> PngRes<Vec<u8>> {
+    let (png, err) = chunks_of_type(filename, chunk_type)?;
+    let messages = match (index, err) {
+        (Some(index), err) => vec![png
+            .decode_nth(chunk_type, index)
+            .map_err(|decode_err| err.unwrap_or(decode_err))?],
+        (None, Some(err)) => return Err(err),
+        (None, None) => png.decode_all(chunk_type)?,
+    };
+
+    let identity = identity.map(self::identity).transpose()?;
+    let mut key = None;
+    let mut decoded = Vec::with_capacity(messages.len());
+    for message in messages {
+        let message = match message {
+            Message::Plain(data) => data,
+            Message::Locked(data) if crypt::is_for_recipients(&data) => {
+                let identity = identity.as_ref().ok_or(PngErr::IdentityNeeded)?;
+                crypt::decrypt_for(&data, identity)?
+            }
+            Message::Locked(data) => {
+                if key.is_none() {
+                    key = Some(passphrase(key_env, key_file, false)?);
+                }
+                crypt::decrypt(&data, key.as_deref().unwrap_or_default())?
+            }
+        };
+        decoded.push(message);
+    }
+    Ok(decoded.join(&b'\n'))
+}
+
+/// Reads just the chunks of `chunk_type` from the file, one at a time, so
+/// the image data is never held in memory. A damaged chunk ends the read,
+/// and its error is returned alongside whatever was found before it.
+fn chunks_of_type(filename: &str, chunk_type: &str) -> PngRes<(Png, Option<PngErr>)> {
+    let mut chunks = Vec::new();
+    for chunk in ChunkReader::png(fs::open(filename)?)? {
+        match chunk {
+            Ok(chunk) if chunk.chunk_type().to_string() == chunk_type => chunks.push(chunk),
+            Ok(_) => (),
+            Err(err) => return Ok((Png::from_chunks(chunks), Some(err))),
+        }
+    }
+
+    Ok((Png::from_chunks(chunks), None))
+}
+
+/// The passphrase in the environment variable `env`, on the first line of
+/// `file`, or else typed at the terminal, twice over if `confirm`.
+fn passphrase(env: Option<&str>, file: Option<&str>, confirm: bool) -> PngRes<Vec<u8>> {
+    let passphrase = match (env, file) {
+        (Some(env), _) => std::env::var(env)
+            .map_err(|_| PngErr::PassphraseNotRead)?
+            .into_bytes(),
+        (None, Some(file)) => {
+            let bytes = fs::read(file)?;
+            let line = bytes
+                .split(|&byte| byte == b'\n')
+                .next()
+                .unwrap_or_default();
+            line.strip_suffix(b"\r").unwrap_or(line).to_vec()
+        }
+        (None, None) => {
+            let prompt = |text: &str| {
+                rpassword::prompt_password(text).map_err(|_| PngErr::PassphraseNotRead)
+            };
+
+            let passphrase = prompt("Passphrase: ")?;
+            if confirm {
+                PngErr::is_or(
+                    prompt("Repeat passphrase: ")? == passphrase,
+                    PngErr::PassphraseMismatch,
+                )?;
+            }
+            passphrase.into_bytes()
+        }
+    };
+
+    PngErr::not_or(passphrase.is_empty(), PngErr::PassphraseNotRead)?;
+    Ok(passphrase)
+}
+
+/// The public keys to encrypt to, each given as hex or the name of a
+/// file holding it.
+fn recipients(keys: &[String]) -> PngRes<Vec<Recipient>> {
+    keys.iter().map(|key| public_key(key)).collect()
+}
+
+/// A public key given as hex or the name of a file holding it.
+fn public_key<K: FromStr<Err = PngErr>>(key: &str) -> PngRes<K> {
+    match K::from_str(key) {
+        Ok(key) => Ok(key),
+        Err(_) => match fs::read(key) {
+            Err(PngErr::FileNotFound) => Err(PngErr::InvalidKey),
+            bytes => K::from_str(&String::from_utf8_lossy(&bytes?)),
+        },
+    }
+}
+
+/// The private key in `file`.
+fn private_key<K: FromStr<Err = PngErr>>(file: &str) -> PngRes<K> {
+    K::from_str(&String::from_utf8_lossy(&fs::read(file)?))
+}
+
+pub fn identity(file: &str) -> PngRes<Identity> {
+    private_key(file)
+}
+
+/// Writes a new private key to `<name>.key`, readable only by its owner,
+/// and its public key to `<name>.pub`, which is returned. The keys are for
+/// signing if `sign`, or else for encrypting.
+pub fn keygen(name: &str, sign: bool) -> PngRes<String> {
+    let (private, public) = if sign {
+        let signer = Signer::generate();
+        (signer.to_string(), signer.verifier().to_string())
+    } else {
+        let identity = Identity::generate();
+        (identity.to_string(), identity.recipient().to_string())
+    };
+
+    let mut key = fs::create_private(&format!("{}.key", name))?;
+    writeln!(key, "{}", private)
+        .and_then(|_| key.flush())
+        .map_err(|_| PngErr::FileNotWritten)?;
+    fs::write(&format!("{}.pub", name), format!("{}\n", public).as_bytes())?;
+    Ok(public)
+}
+
+/// Signs the messages of the type with the private key in `key_file`, and
+/// the pixels too if `pixels`.
+pub fn sign(filename: &str, chunk_type: &str, key_file: &str, pixels: bool) -> PngRes {
+    let signer: Signer = private_key(key_file)?;
     Png::load(filename)?
-        .discard_unchecked(chunk_type)?
+        .sign(chunk_type, &signer, pixels)?
         .save(filename)
 }
 
-pub fn print(filename: &str) -> PngRes<String> {
-    let png = Png::load(filename)?;
+/// Reports on every signature, and whether they all hold. With a
+/// `signer`, one of them must also be a valid signature by that key.
+pub fn verify(filename: &str, signer: Option<&str>) -> PngRes<(String, PngRes)> {
+    let bytes = fs::read(filename)?;
+    let verified = Png::borrowed(&bytes)?.verify()?;
+    PngErr::not_or(verified.is_empty(), PngErr::NotSigned)?;
+
+    let report = verified
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if let Some(signer) = signer {
+        let signer: Verifier = public_key(signer)?;
+        let mut by_signer = verified.iter().filter(|verified| verified.signer == signer);
+        let outcome = match by_signer.clone().next() {
+            None => Err(PngErr::NotSigned),
+            Some(_) => PngErr::is_or(by_signer.any(Verification::is_valid), PngErr::NotVerified),
+        };
+        if outcome.is_err() {
+            return Ok((report, outcome));
+        }
+    }
+
+    let valid = verified.iter().all(Verification::is_valid);
+    Ok((report, PngErr::is_or(valid, PngErr::NotVerified)))
+}
+
+/// Writes decoded data to `out` or standard output as it is, or prints it
+/// as a line of text, which it must then be.
+pub fn output(data: Vec<u8>, out: Option<&str>, raw: bool) -> PngRes {
+    if let Some(out) = out {
+        return fs::write(out, &data);
+    }
+
+    if raw {
+        let mut stdout = io::stdout();
+        return stdout
+            .write_all(&data)
+            .and_then(|_| stdout.flush())
+            .map_err(|_| PngErr::WriteError);
+    }
+
+    let text = String::from_utf8(data).map_err(|_| PngErr::NotUTF8)?;
+    println!("{}", text);
+    Ok(())
+}
+
+/// Removes every chunk of the type if `all`, or else the `index`th, or the
+/// first if there is no index.
+pub fn remove(filename: &str, chunk_type: &str, index: Option<usize>, all: bool) -> PngRes {
+    let mut png = Png::load(filename)?;
+    match (index, all) {
+        (_, true) => png.discard_all(chunk_type)?,
+        (Some(index), false) => png.discard_nth(chunk_type, index)?,
+        (None, false) => png.discard(chunk_type)?,
+    }
+    .save(filename)
+}
+
+pub fn remove_unchecked(
+    filename: &str,
+    chunk_type: &str,
+    index: Option<usize>,
+    all: bool,
+) -> PngRes {
+    let mut png = Png::load(filename)?;
+    match (index, all) {
+        (_, true) => png.discard_all_unchecked(chunk_type)?,
+        (Some(index), false) => png.discard_nth_unchecked(chunk_type, index)?,
+        (None, false) => png.discard_unchecked(chunk_type)?,
+    }
+    .save(filename)
+}
+
+pub fn print(filename: &str, chunk_type: Option<&str>, lenient: bool) -> PngRes<String> {
+    if lenient {
+        return print_lenient(filename, chunk_type);
+    }
+
+    let mut listing = String::new();
+    let mut listed = 0;
+    let mut chunks = Vec::new();
+
+    // Image data is listed but not kept, so only metadata is held in memory.
+    for chunk in ChunkReader::png(fs::open(filename)?)? {
+        let chunk = chunk?;
+        let name = chunk.chunk_type().to_string();
+        if chunk_type.is_none_or(|chunk_type| name == chunk_type) {
+            listing.push_str(&format!("{}: {}\n", listed, chunk));
+            listed += 1;
+        }
+
+        if name != "IDAT" {
+            chunks.push(chunk);
+        }
+    }
+
+    Ok(format!(
+        "{}\n{}",
+        listing,
+        summary(&Png::from_chunks(chunks))
+    ))
+}
+
+/// Lists whatever chunks can be salvaged from a damaged file, followed by
+/// what was wrong with it.
+fn print_lenient(filename: &str, chunk_type: Option<&str>) -> PngRes<String> {
+    let recovered = Png::load_lenient(filename)?;
+    let mut listing = listing(&recovered.png, chunk_type);
+
+    for problem in &recovered.problems {
+        listing.push_str(&format!("Recovered from {}\n", problem));
+    }
+
+    Ok(format!("{}\n{}", listing, summary(&recovered.png)))
+}
+
+/// One line per chunk, or per chunk of `chunk_type` numbered among
+/// themselves so the numbers work with `--index`.
+fn listing<C: AsChunkRef>(png: &Png<C>, chunk_type: Option<&str>) -> String {
+    match chunk_type {
+        Some(chunk_type) => png
+            .chunks_by_type(chunk_type)
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| format!("{}: {}\n", index, chunk.as_chunk_ref()))
+            .collect(),
+        None => png.to_string(),
+    }
+}
+
+fn summary<C: AsChunkRef>(png: &Png<C>) -> String {
+    // Only the metadata is copied out, never the image data.
+    let png = Png::from_chunks(
+        png.chunks()
+            .iter()
+            .map(AsChunkRef::as_chunk_ref)
+            .filter(|chunk| chunk.chunk_type().to_string() != "IDAT")
+            .map(Chunk::from)
+            .collect(),
+    );
+
     let ihdr = match png.chunk_by_type("IHDR") {
-        Some(ihdr) => match Chunk::ihdr_to_dimensions(ihdr) {
-            Ok((width, height)) => format!("Image Dimensions: {}x{}", width, height),
+        Some(ihdr) => match Ihdr::try_from(ihdr) {
+            Ok(ihdr) => format!("Image Dimensions: {}", ihdr),
             Err(_) => String::from("Image contains invalid IHDR chunk!"),
         },
         None => String::from("Image does not contain IHDR chunk."),
     };
 
-    Ok(format!("{}\n{}", png.to_string(), ihdr))
+    let color_space = match ColorSpace::from_chunks(png.chunks()) {
+        Ok(color_space) => format!("Color Space: {}", color_space),
+        Err(_) => String::from("Image contains invalid color space chunks!"),
+    };
+
+    format!("{}\n{}", ihdr, color_space)
 }
 
 pub fn scrub(filename: &str) -> PngRes {
@@ -51,7 +421,150 @@ pub fn scrub(filename: &str) -> PngRes {
     png.save(filename)
 }
 
-pub fn generate() -> PngRes {
+/// Returns the report and whether the file is valid. Warnings are listed
+/// but don't make the file invalid.
+pub fn validate(filename: &str) -> PngRes<(String, bool)> {
+    let violations = png::validate(&fs::read(filename)?);
+    let valid = violations
+        .iter()
+        .all(|violation| violation.problem.is_warning());
+
+    let mut lines: Vec<String> = violations
+        .iter()
+        .map(|violation| {
+            if violation.problem.is_warning() {
+                format!("warning: {}", violation)
+            } else {
+                violation.to_string()
+            }
+        })
+        .collect();
+    if valid {
+        lines.insert(0, format!("{} conforms to the PNG specification", filename));
+    }
+
+    Ok((lines.join("\n"), valid))
+}
+
+pub fn optimize(filename: &str, output: Option<&str>, strip: bool) -> PngRes<String> {
+    let png = Png::load(filename)?;
+    let before = png.as_bytes().len();
+    let optimized = png.optimize(strip)?;
+    let after = optimized.as_bytes().len();
+
+    if after >= before {
+        return Ok(format!(
+            "{} is already as small as pngme can make it ({} bytes)",
+            filename, before
+        ));
+    }
+
+    let output = output.unwrap_or(filename);
+    optimized.save(output)?;
+
+    Ok(format!(
+        "Wrote {}: {} -> {} bytes, saved {} ({:.1}%)",
+        output,
+        before,
+        after,
+        before - after,
+        (before - after) as f64 * 100.0 / before as f64
+    ))
+}
+
+pub fn repair(filename: &str, output: &str) -> PngRes<String> {
+    let (png, repairs) = Png::repair(&fs::read(filename)?)?;
+    png.save(output)?;
+
+    if repairs.is_empty() {
+        return Ok(format!("Nothing to repair, wrote a copy to {}", output));
+    }
+
+    let changes = repairs
+        .iter()
+        .map(|repair| format!("Repaired: {}", repair))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    Ok(format!("{}\nWrote {}", changes, output))
+}
+
+pub fn frames(filename: &str) -> PngRes<String> {
+    let animation: Animation = Animation::from_png(&Png::load(filename)?)?;
+    let plays = match animation.plays {
+        0 => String::from("forever"),
+        plays => format!("{} times", plays),
+    };
+
+    let mut listing = format!(
+        "Canvas: {}x{}, {} frames, plays {}\n",
+        animation.width,
+        animation.height,
+        animation.frames.len(),
+        plays
+    );
+
+    if animation.default_image.is_some() {
+        listing.push_str("Has a separate default image\n");
+    }
+
+    for (index, frame) in animation.frames.iter().enumerate() {
+        listing.push_str(&format!(
+            "{}: {}x{} at ({}, {}), {}/{}s ({:.3}s), dispose {}, blend {}\n",
+            index,
+            frame.img.width(),
+            frame.img.height(),
+            frame.x,
+            frame.y,
+            frame.delay_num,
+            frame.delay_den,
+            frame.delay(),
+            frame.dispose,
+            frame.blend
+        ));
+    }
+
+    Ok(listing.trim_end().to_string())
+}
+
+pub fn extract(filename: &str, prefix: &str, raw: bool) -> PngRes {
+    let animation: Animation = Animation::from_png(&Png::load(filename)?)?;
+    let imgs = if raw {
+        animation
+            .frames
+            .into_iter()
+            .map(|frame| frame.img)
+            .collect()
+    } else {
+        animation.render()?
+    };
+
+    for (index, img) in imgs.into_iter().enumerate() {
+        Png::from_img(img)?.save(&format!("{}{:03}.png", prefix, index))?;
+    }
+
+    Ok(())
+}
+
+pub fn assemble(output: &str, frames: &[String], delay: u16, plays: u32) -> PngRes {
+    let imgs = frames
+        .iter()
+        .map(|file| Png::load(file)?.to_img())
+        .collect::<PngRes<Vec<Img>>>()?;
+
+    let (width, height) = imgs.first().ok_or(PngErr::InvalidAnimation)?.dimensions();
+    PngErr::is_or(
+        imgs.iter().all(|img| img.dimensions() == (width, height)),
+        PngErr::DataLengthMismatch,
+    )?;
+
+    let mut animation = Animation::new(width, height);
+    animation.plays = plays;
+    animation.frames = imgs.into_iter().map(|img| Frame::new(img, delay)).collect();
+    animation.to_png_with(&EncodeOptions::new())?.save(output)
+}
+
+pub fn generate(options: &EncodeOptions) -> PngRes {
     let mut gradient = Img::new(600, 600);
 
     let mut slice = gradient.slice(0..=2, 0..=2);
@@ -68,7 +581,7 @@ pub fn generate() -> PngRes {
         }
     }
 
-    let mut gradient_png = Png::from_img(gradient)?;
+    let mut gradient_png = Png::from_img_with(gradient, options)?;
 
     gradient_png
         .encode("pgMe", "I'm the gradients image.")?
@@ -95,11 +608,23 @@ pub fn generate() -> PngRes {
         .copy(300, 50)
         .copy(300, 300);
 
-    Png::from_img(squares)?
-        .encode("pgMe", "I'm the squares image.")?
-        .save("squares.png")?;
+    // These are written straight to disk rather than assembled in memory.
+    let message = |text: &str| {
+        ChunkType::from_str("pgMe").map(|ty| Chunk::new(ty, Message::from(text).to_bytes()))
+    };
+
+    Png::write_img(
+        &squares,
+        fs::create("squares.png")?,
+        options,
+        vec![message("I'm the squares image.")?],
+    )?;
 
-    Png::from_img(small)?
-        .encode("pgMe", "I'm the small image.")?
-        .save("small.png")
+    Png::write_img(
+        &small,
+        fs::create("small.png")?,
+        options,
+        vec![message("I'm the small image.")?],
+    )
+    .map(drop)
 }
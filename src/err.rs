@@ -2,7 +2,7 @@ use std::fmt;
 
 pub type PngRes<T = ()> = Result<T, PngErr>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PngErr {
     DivisionByZero,
     IntOverflow,
@@ -22,12 +22,41 @@ pub enum PngErr {
     ExpectReservedBit,
     ExpectPrivate,
     ChunkNotFound,
+    InvalidPlacement,
+    MessageTooLarge,
+    MissingPart,
+    InvalidPart,
+    SplitTooSmall,
+    InvalidCipher,
+    WrongKey,
+    TamperedMessage,
+    MessageLocked,
+    PassphraseNotRead,
+    PassphraseMismatch,
+    InvalidKey,
+    IdentityNeeded,
+    InvalidSignature,
+    NotSigned,
+    NotVerified,
     FileNotFound,
     FileNotRead,
     FileNotWritten,
+    FileExists,
+    ReadError,
+    WriteError,
     DataLengthMismatch,
     CompressError,
     DecompressError,
+    InvalidFilter,
+    InvalidOption,
+    InvalidColorType,
+    InvalidBitDepth,
+    InvalidPalette,
+    InvalidTransparency,
+    InvalidColorSpace,
+    InvalidAnimation,
+    AnimationChunk,
+    NotConformant,
 }
 
 use PngErr::*;
@@ -65,12 +94,45 @@ impl fmt::Display for PngErr {
             ExpectReservedBit => "Expected an uppercase character at position 3 (reserved bit).",
             ExpectPrivate => "Expected a lowercase character at position 2 (private).",
             ChunkNotFound => "That chunk was not found.",
+            InvalidPlacement => "A chunk can only be placed between IHDR and IEND.",
+            MessageTooLarge => "The message is too large for one chunk, so must be split.",
+            MissingPart => "A part of the split message is missing.",
+            InvalidPart => "The parts of the split message are corrupt or don't match.",
+            SplitTooSmall => "Each part must be more than 13 bytes, to hold its header.",
+            InvalidCipher => "The encrypted message is malformed or uses unknown settings.",
+            WrongKey => "That passphrase or key does not unlock the message.",
+            TamperedMessage => "The encrypted message has been altered or corrupted.",
+            MessageLocked => "The message is encrypted, so a passphrase or key is needed.",
+            PassphraseNotRead => "The passphrase could not be read, or was empty.",
+            PassphraseMismatch => "The passphrases entered did not match.",
+            InvalidKey => "That is not a valid key, or no key was given.",
+            IdentityNeeded => {
+                "The message is encrypted to public keys, so a private key is needed."
+            }
+            InvalidSignature => "A signature chunk is malformed.",
+            NotSigned => "No signature was found, or none by that key.",
+            NotVerified => {
+                "The file has changed since it was signed, or a signature is not genuine."
+            }
             FileNotFound => "That file was not found.",
             FileNotRead => "Could not read that file.",
             FileNotWritten => "Could not write that file.",
+            FileExists => "That file already exists.",
+            ReadError => "An error occurred reading the input.",
+            WriteError => "An error occurred writing the output.",
             DataLengthMismatch => "Data does not align to the image dimensions.",
             CompressError => "An error occurred compressing image data.",
             DecompressError => "An error occurred decompressing image data.",
+            InvalidFilter => "A scanline used an unknown filter type.",
+            InvalidOption => "That option value was not recognised.",
+            InvalidColorType => "The IHDR chunk has an unknown color type.",
+            InvalidBitDepth => "The IHDR bit depth is not allowed for its color type.",
+            InvalidPalette => "The palette was missing or a pixel referenced a missing entry.",
+            InvalidTransparency => "The tRNS chunk does not match the image color type.",
+            InvalidColorSpace => "A gAMA, cHRM, sRGB or iCCP chunk was malformed.",
+            InvalidAnimation => "The acTL, fcTL or fdAT chunks were malformed or out of order.",
+            AnimationChunk => "That chunk type is reserved for APNG animation.",
+            NotConformant => "The file does not conform to the PNG specification.",
         };
 
         write!(f, "{}", message)
@@ -1,15 +1,22 @@
-use crate::err::*;
-use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use crate::{
+    err::*,
+    png::{Deflate, ZlibWriter},
+};
+use flate2::read::ZlibDecoder;
 use std::{
-    fs,
-    io::{ErrorKind, Read, Write},
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
 };
 
 pub fn compress(data: &[u8]) -> PngRes<Vec<u8>> {
-    let mut compress = ZlibEncoder::new(Vec::new(), Compression::default());
+    compress_with(data, &Deflate::default())
+}
+
+pub fn compress_with(data: &[u8], deflate: &Deflate) -> PngRes<Vec<u8>> {
+    let mut compress = ZlibWriter::new(Vec::new(), deflate)?;
 
     compress.write_all(data).or(Err(PngErr::CompressError))?;
-    compress.finish().or(Err(PngErr::CompressError))
+    compress.finish()
 }
 
 pub fn decompress(data: &[u8]) -> PngRes<Vec<u8>> {
@@ -20,13 +27,47 @@ pub fn decompress(data: &[u8]) -> PngRes<Vec<u8>> {
         .map_or(Err(PngErr::DecompressError), |_| Ok(output))
 }
 
+pub fn open(filename: &str) -> PngRes<BufReader<File>> {
+    File::open(filename)
+        .map(BufReader::new)
+        .map_err(|err| match err.kind() {
+            ErrorKind::NotFound => PngErr::FileNotFound,
+            _ => PngErr::FileNotRead,
+        })
+}
+
 pub fn read(filename: &str) -> PngRes<Vec<u8>> {
-    fs::read(filename).map_err(|err| match err.kind() {
-        ErrorKind::NotFound => PngErr::FileNotFound,
-        _ => PngErr::FileNotRead,
-    })
+    let mut bytes = Vec::new();
+    open(filename)?
+        .read_to_end(&mut bytes)
+        .map_or(Err(PngErr::FileNotRead), |_| Ok(bytes))
+}
+
+pub fn create(filename: &str) -> PngRes<BufWriter<File>> {
+    File::create(filename)
+        .map(BufWriter::new)
+        .map_err(|_| PngErr::FileNotWritten)
+}
+
+/// Creates a file only its owner can read, failing if it already exists.
+pub fn create_private(filename: &str) -> PngRes<BufWriter<File>> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options
+        .open(filename)
+        .map(BufWriter::new)
+        .map_err(|err| match err.kind() {
+            ErrorKind::AlreadyExists => PngErr::FileExists,
+            _ => PngErr::FileNotWritten,
+        })
 }
 
-pub fn write(filename: &str, data: &[u8]) -> PngRes {
-    fs::write(filename, data).map_err(|_| PngErr::FileNotWritten)
+pub fn write(filename: &str, bytes: &[u8]) -> PngRes {
+    let mut file = create(filename)?;
+    file.write_all(bytes)
+        .and_then(|_| file.flush())
+        .map_err(|_| PngErr::FileNotWritten)
 }
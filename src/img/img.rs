@@ -2,22 +2,24 @@ use super::{rect::Rect, rect_slice::RectSlice};
 use crate::{
     area, calc, convert,
     err::{PngErr::*, *},
-    fs,
-    png::{self, Chunk, Png},
-    Image, Quad,
+    png::{ColorSpace, DecodeOptions, Header, Png, RenderingIntent},
+    Color, Image, Quad,
 };
 
 use std::ops::RangeBounds;
 
+/// An RGBA image. Pixels are `u32` (8 bits per channel) unless another
+/// [`Color`] is chosen, such as `u64` for 16 bits per channel.
 #[derive(Debug, Clone)]
-pub struct Img {
+pub struct Img<P: Color = u32> {
     width: u32,
     height: u32,
-    data: Vec<u32>,
+    data: Vec<P>,
     filter: Vec<u8>,
+    color_space: ColorSpace,
 }
 
-impl Quad for Img {
+impl<P: Color> Quad for Img<P> {
     fn width(&self) -> u32 {
         self.width
     }
@@ -27,48 +29,54 @@ impl Quad for Img {
     }
 }
 
-impl Image for Img {
-    fn to_vec(self) -> Vec<u32> {
+impl<P: Color> Image<P> for Img<P> {
+    fn to_vec(self) -> Vec<P> {
         self.data
     }
 
-    fn clone_to_vec(&self) -> Vec<u32> {
+    fn clone_to_vec(&self) -> Vec<P> {
         self.data.clone()
     }
 }
 
-impl Img {
+impl<P: Color> Img<P> {
     pub fn len(&self) -> usize {
         self.data.len()
     }
 
-    pub fn new(width: u32, height: u32) -> Img {
-        Self::new_bg(width, height, 0)
+    pub fn new(width: u32, height: u32) -> Self {
+        Self::new_bg(width, height, P::default())
     }
 
-    pub fn new_bg(width: u32, height: u32, bg: u32) -> Img {
+    pub fn new_bg(width: u32, height: u32, bg: P) -> Self {
         Self {
             width,
             height,
             data: vec![bg; area!(width, height)],
             filter: vec![0; convert!(ex usize; height)],
+            color_space: ColorSpace::default(),
         }
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
-        self.data
-            .chunks(self.width().try_into().unwrap())
-            .map(|chunk| chunk.into_iter().flat_map(|px| px.to_be_bytes()))
-            .zip(self.filter.iter())
-            .flat_map(|(row, ft)| ft.to_be_bytes().into_iter().chain(row))
-            .collect()
+    pub fn filters(&self) -> &[u8] {
+        &self.filter
+    }
+
+    /// The color space chunks read from, or to be written to, a PNG.
+    pub fn color_space(&self) -> &ColorSpace {
+        &self.color_space
+    }
+
+    pub fn set_color_space(&mut self, color_space: ColorSpace) -> &mut Self {
+        self.color_space = color_space;
+        self
     }
 
-    pub fn data(&self) -> &Vec<u32> {
+    pub fn data(&self) -> &Vec<P> {
         &self.data
     }
 
-    pub fn data_mut(&mut self) -> &mut Vec<u32> {
+    pub fn data_mut(&mut self) -> &mut Vec<P> {
         self.data.as_mut()
     }
 
@@ -76,7 +84,7 @@ impl Img {
         &mut self,
         xx2: impl RangeBounds<u32>,
         yy2: impl RangeBounds<u32>,
-    ) -> RectSlice<'_> {
+    ) -> RectSlice<'_, P> {
         let rs = RectSlice::new(
             self,
             Rect::from_range(xx2, yy2).constrain(self.width(), self.height()),
@@ -84,20 +92,26 @@ impl Img {
         rs
     }
 
-    fn from_parts(width: u32, height: u32, data: Vec<u32>, filter: Vec<u8>) -> PngRes<Img> {
+    pub(crate) fn from_parts(
+        width: u32,
+        height: u32,
+        data: Vec<P>,
+        filter: Vec<u8>,
+    ) -> PngRes<Self> {
         Ok(Self {
             width,
             height,
             data,
             filter,
+            color_space: ColorSpace::default(),
         })
     }
 
-    pub fn from_vec(width: u32, height: u32, data: Vec<u32>) -> Self {
+    pub fn from_vec(width: u32, height: u32, data: Vec<P>) -> Self {
         let area = area!(width, height);
         let mut data = data;
         if area > data.len() {
-            data.append(&mut vec![0u32; area - data.len()]);
+            data.append(&mut vec![P::default(); area - data.len()]);
         }
 
         Self {
@@ -105,10 +119,11 @@ impl Img {
             height,
             data,
             filter: vec![0; convert!(ex usize; height)],
+            color_space: ColorSpace::default(),
         }
     }
 
-    pub fn from_vec_2d(data: Vec<Vec<u32>>) -> PngRes<Self> {
+    pub fn from_vec_2d(data: Vec<Vec<P>>) -> PngRes<Self> {
         let height = data.len();
         let width = data.get(0).ok_or_else(|| ZeroWidth)?.len();
 
@@ -127,56 +142,37 @@ impl Img {
     pub fn reset_filter(&mut self) {
         self.filter = vec![0; convert!(ex usize; self.height)];
     }
-}
 
-impl TryFrom<Png> for Img {
-    type Error = PngErr;
-    fn try_from(png: Png) -> PngRes<Img> {
-        let header = png.chunk_by_type("IHDR").ok_or(PngErr::InvalidHeader)?;
-        let (width, height) = Chunk::ihdr_to_dimensions(&header)?;
-
-        // std::mem::transmute...
-
-        let chunk_size = convert!(usize; width)?
-            .checked_mul(4)
-            .ok_or(PngErr::IntOverflow)?
-            .checked_add(1)
-            .ok_or(PngErr::IntOverflow)?;
-
-        let data = fs::decompress(
-            png.chunks()
-                .into_iter()
-                .filter(|&chunk| chunk.chunk_type().to_string() == "IDAT")
-                .map(|chunk| chunk.data())
-                .flatten()
-                .copied()
-                .collect::<Vec<u8>>()
-                .as_slice(),
-        )?;
-
-        let scans = data
-            .len()
-            .checked_div(chunk_size)
-            .ok_or(PngErr::DataLengthMismatch)?;
-
-        let uheight = convert!(usize; height)?;
-        if scans != uheight {
-            return Err(PngErr::DataLengthMismatch);
-        }
+    /// Decode `png`, converting its pixels to sRGB if `options` asks for
+    /// it. The color space chunks are kept so they can be written again.
+    pub fn from_png_with(png: Png, options: &DecodeOptions) -> PngRes<Self> {
+        let header = Header::from_chunks(png.chunks())?;
+        let (width, height) = (header.ihdr.width, header.ihdr.height);
 
-        let mut filter = Vec::with_capacity(uheight);
-        let data: Vec<u32> = data
-            .chunks(chunk_size)
-            .enumerate()
-            .map(|(i, scan)| {
-                filter.push(scan[0]);
-                (&scan[1..])
-                    .chunks(4)
-                    .map(|px| u32::from_be_bytes(png::segment4(px).unwrap()))
-            })
-            .flatten()
+        let idat: Vec<u8> = png
+            .chunks()
+            .iter()
+            .filter(|&chunk| chunk.chunk_type().to_string() == "IDAT")
+            .flat_map(|chunk| chunk.data())
+            .copied()
             .collect();
 
-        Self::from_parts(width, height, data, filter)
+        let (pixels, filter) = header.pixels(&idat, width, height)?;
+
+        let mut img = Self::from_parts(width, height, pixels, filter)?;
+        img.color_space = header.color_space;
+
+        if options.srgb && img.color_space.to_srgb(&mut img.data) {
+            img.color_space = ColorSpace::srgb(RenderingIntent::Perceptual);
+        }
+
+        Ok(img)
+    }
+}
+
+impl<P: Color> TryFrom<Png> for Img<P> {
+    type Error = PngErr;
+    fn try_from(png: Png) -> PngRes<Self> {
+        Self::from_png_with(png, &DecodeOptions::default())
     }
 }
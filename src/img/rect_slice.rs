@@ -1,14 +1,14 @@
 use super::{img::Img, rect::Rect};
-use crate::{calc, convert, err::*, Image, Quad};
+use crate::{calc, convert, err::*, Color, Image, Quad};
 use std::ops::RangeBounds;
 
 #[derive(Debug)]
-pub struct RectSlice<'a> {
-    img: &'a mut Img,
+pub struct RectSlice<'a, P: Color = u32> {
+    img: &'a mut Img<P>,
     rect: Rect,
 }
 
-impl Quad for RectSlice<'_> {
+impl<P: Color> Quad for RectSlice<'_, P> {
     fn width(&self) -> u32 {
         self.rect.width()
     }
@@ -18,18 +18,18 @@ impl Quad for RectSlice<'_> {
     }
 }
 
-impl Image for RectSlice<'_> {
-    fn to_vec(self) -> Vec<u32> {
+impl<P: Color> Image<P> for RectSlice<'_, P> {
+    fn to_vec(self) -> Vec<P> {
         self.clone_to_vec()
     }
 
-    fn clone_to_vec(&self) -> Vec<u32> {
+    fn clone_to_vec(&self) -> Vec<P> {
         self.iter().copied().collect()
     }
 }
 
-impl<'a> RectSlice<'a> {
-    pub fn new(img: &'a mut Img, rect: Rect) -> Self {
+impl<'a, P: Color> RectSlice<'a, P> {
+    pub fn new(img: &'a mut Img<P>, rect: Rect) -> Self {
         Self { img, rect }
     }
 
@@ -54,24 +54,24 @@ impl<'a> RectSlice<'a> {
         self.set_rect(self.rect.pos(x, y))
     }
 
-    pub fn fill(&mut self, col: u32) -> &mut Self {
+    pub fn fill(&mut self, col: P) -> &mut Self {
         self.iter_mut().for_each(|px| *px = col);
         self
     }
 
     pub fn clear(&mut self) -> &mut Self {
-        self.iter_mut().for_each(|px| *px = 0);
+        self.iter_mut().for_each(|px| *px = P::default());
         self
     }
 
-    pub fn copy_from(&mut self, data: &[u32]) -> &mut Self {
+    pub fn copy_from(&mut self, data: &[P]) -> &mut Self {
         self.iter_mut()
             .zip(data.iter())
             .for_each(|(curr, new)| *curr = *new);
         self
     }
 
-    pub fn copy_each(&mut self, x: u32, y: u32, filter: impl Fn(&u32, &u32) -> u32) -> &mut Self {
+    pub fn copy_each(&mut self, x: u32, y: u32, filter: impl Fn(&P, &P) -> P) -> &mut Self {
         let from = self.rect;
         let to = self
             .rect
@@ -96,26 +96,26 @@ impl<'a> RectSlice<'a> {
         self.copy_each(x, y, |from, _| *from)
     }
 
-    pub fn clone_to_img(&self) -> Img {
+    pub fn clone_to_img(&self) -> Img<P> {
         Img::from_vec(self.rect.width(), self.rect.height(), self.clone_to_vec())
     }
 
-    pub fn iter(&self) -> Iter<'a, '_> {
+    pub fn iter(&self) -> Iter<'a, '_, P> {
         Iter::new(self)
     }
 
-    pub fn iter_mut(&mut self) -> IterMut<'a, '_> {
+    pub fn iter_mut(&mut self) -> IterMut<'a, '_, P> {
         IterMut::new(self)
     }
 }
 
-pub struct Iter<'a, 'b> {
-    slice: &'b RectSlice<'a>,
+pub struct Iter<'a, 'b, P: Color = u32> {
+    slice: &'b RectSlice<'a, P>,
     idx: Indexer,
 }
 
-impl<'a, 'b> Iter<'a, 'b> {
-    pub fn new(slice: &'b RectSlice<'a>) -> Self {
+impl<'a, 'b, P: Color> Iter<'a, 'b, P> {
+    pub fn new(slice: &'b RectSlice<'a, P>) -> Self {
         Self {
             slice,
             idx: Indexer::from_slice(slice.rect(), slice.img.width()).into_iter(),
@@ -123,8 +123,8 @@ impl<'a, 'b> Iter<'a, 'b> {
     }
 }
 
-impl<'a, 'b> Iterator for Iter<'a, 'b> {
-    type Item = &'b u32;
+impl<'b, P: Color> Iterator for Iter<'_, 'b, P> {
+    type Item = &'b P;
     fn next(&mut self) -> Option<Self::Item> {
         self.idx
             .next()
@@ -132,27 +132,27 @@ impl<'a, 'b> Iterator for Iter<'a, 'b> {
     }
 }
 
-pub struct IterMut<'a, 'b> {
-    slice: &'b mut RectSlice<'a>,
+pub struct IterMut<'a, 'b, P: Color = u32> {
+    slice: &'b mut RectSlice<'a, P>,
     idx: Indexer,
 }
 
-impl<'a, 'b> IterMut<'a, 'b> {
-    pub fn new(slice: &'b mut RectSlice<'a>) -> Self {
+impl<'a, 'b, P: Color> IterMut<'a, 'b, P> {
+    pub fn new(slice: &'b mut RectSlice<'a, P>) -> Self {
         let idx = Indexer::from_slice(slice.rect(), slice.img.width()).into_iter();
         Self { slice, idx }
     }
 }
 
-impl<'a, 'b> Iterator for IterMut<'a, 'b> {
-    type Item = &'b mut u32;
+impl<'b, P: Color> Iterator for IterMut<'_, 'b, P> {
+    type Item = &'b mut P;
     fn next(&mut self) -> Option<Self::Item> {
         self.idx.next().map_or(None, |idx| {
             self.slice
                 .img
                 .data_mut()
                 .get_mut(idx)
-                .map(|px| unsafe { &mut *(px as *mut u32) })
+                .map(|px| unsafe { &mut *(px as *mut P) })
         })
     }
 }
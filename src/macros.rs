@@ -61,7 +61,7 @@ macro_rules! col {
         u32::from_be_bytes([$r, $g, $b, 255])
     }};
     ($col: expr) => {{
-        let [r_, rg, gb, ba] = $col._color_value().to_be_bytes();
+        let [r_, rg, gb, ba] = <u32 as Color>::_color_value(&$col).to_be_bytes();
         if (r_ > 0) {
             col!(r_, rg, gb, ba)
         } else {
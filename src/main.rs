@@ -10,6 +10,8 @@ mod traits;
 mod macros;
 
 use args::PngME::{self, *};
+use commands::Lock;
+use png::EncodeOptions;
 
 pub(crate) use err::*;
 pub(crate) use traits::*;
@@ -22,7 +24,17 @@ fn exec(command: PngME) -> PngRes {
             file,
             chunk_type,
             message,
+            input,
+            stdin,
             unchecked,
+            at,
+            index,
+            add,
+            split,
+            recipients,
+            encrypt,
+            key_env,
+            key_file,
         } => {
             let encode = if unchecked {
                 commands::encode_unchecked
@@ -30,30 +42,128 @@ fn exec(command: PngME) -> PngRes {
                 commands::encode
             };
 
-            encode(&file, &chunk_type, &message.join(" "))?;
+            let lock = if !recipients.is_empty() {
+                Lock::Recipients(&recipients)
+            } else if encrypt || key_env.is_some() || key_file.is_some() {
+                Lock::Passphrase {
+                    env: key_env.as_deref(),
+                    file: key_file.as_deref(),
+                }
+            } else {
+                Lock::None
+            };
+
+            let index = (!add).then_some(index);
+            let message = commands::message(&message, input.as_deref(), stdin)?;
+            encode(&file, &chunk_type, &message, lock, at, index, split)?;
         }
-        Decode { file, chunk_type } => {
-            let message = commands::decode(&file, &chunk_type)?;
-            println!("{}", message);
+        Decode {
+            file,
+            chunk_type,
+            index,
+            all,
+            out,
+            raw,
+            key_env,
+            key_file,
+            identity,
+        } => {
+            let message = commands::decode(
+                &file,
+                &chunk_type,
+                (!all).then_some(index),
+                key_env.as_deref(),
+                key_file.as_deref(),
+                identity.as_deref(),
+            )?;
+            commands::output(message, out.as_deref(), raw)?;
         }
         Remove {
             file,
             chunk_type,
             unchecked,
+            index,
+            all,
         } => {
             let remove = if unchecked {
                 commands::remove_unchecked
             } else {
                 commands::remove
             };
-            remove(&file, &chunk_type)?;
+            remove(&file, &chunk_type, index, all)?;
         }
-        Print { file } => {
-            let content = commands::print(&file)?;
+        Print {
+            file,
+            chunk_type,
+            lenient,
+        } => {
+            let content = commands::print(&file, chunk_type.as_deref(), lenient)?;
             println!("{}", content);
         }
         Scrub { file } => commands::scrub(&file)?,
-        Generate => commands::generate()?,
+        Validate { file } => {
+            let (report, valid) = commands::validate(&file)?;
+            println!("{}", report);
+            PngErr::is_or(valid, PngErr::NotConformant)?;
+        }
+        Optimize {
+            file,
+            output,
+            strip,
+        } => {
+            let report = commands::optimize(&file, output.as_deref(), strip)?;
+            println!("{}", report);
+        }
+        Repair { file, output } => {
+            let report = commands::repair(&file, &output)?;
+            println!("{}", report);
+        }
+        Frames { file } => {
+            let content = commands::frames(&file)?;
+            println!("{}", content);
+        }
+        Extract { file, prefix, raw } => commands::extract(&file, &prefix, raw)?,
+        Assemble {
+            output,
+            frames,
+            delay,
+            plays,
+        } => commands::assemble(&output, &frames, delay, plays)?,
+        Keygen { name, sign } => {
+            let public = commands::keygen(&name, sign)?;
+            println!("{}", public);
+        }
+        Sign {
+            file,
+            chunk_type,
+            key,
+            pixels,
+        } => commands::sign(&file, &chunk_type, &key, pixels)?,
+        Verify { file, signer } => {
+            let (report, verified) = commands::verify(&file, signer.as_deref())?;
+            println!("{}", report);
+            verified?;
+        }
+        Generate {
+            filter,
+            interlace,
+            no_reduce,
+            level,
+            strategy,
+            window,
+            idat_size,
+            threads,
+        } => commands::generate(
+            &EncodeOptions::new()
+                .filter(filter)
+                .interlace(interlace)
+                .reduce(!no_reduce)
+                .level(level)
+                .strategy(strategy)
+                .window(window)
+                .idat_size(idat_size)
+                .threads(threads),
+        )?,
     })
 }
 
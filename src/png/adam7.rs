@@ -0,0 +1,122 @@
+use crate::{area, calc, convert, err::*};
+
+/// Origin and spacing (x, y, dx, dy) of the seven Adam7 passes.
+const PASSES: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// One of the reduced images making up an interlaced PNG.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Pass {
+    x: u32,
+    y: u32,
+    dx: u32,
+    dy: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Pass {
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Index into the full image of each pixel in this pass, in order.
+    fn indices(self, img_width: u32) -> impl Iterator<Item = usize> {
+        let img_width = convert!(ex usize; img_width);
+        (0..self.height).flat_map(move |py| {
+            (0..self.width).map(move |px| {
+                let (x, y) = convert!(ex usize; self.x + px * self.dx, self.y + py * self.dy);
+                y * img_width + x
+            })
+        })
+    }
+
+    /// Collect this pass's pixels out of a full image.
+    pub fn extract<T: Copy>(&self, data: &[T], img_width: u32) -> Vec<T> {
+        let mut pixels = Vec::with_capacity(area!(self.width, self.height));
+        pixels.extend(self.indices(img_width).map(|idx| data[idx]));
+        pixels
+    }
+
+    /// Write this pass's pixels into their positions in a full image.
+    pub fn scatter<T: Copy>(&self, pixels: &[T], data: &mut [T], img_width: u32) {
+        for (idx, px) in self.indices(img_width).zip(pixels) {
+            data[idx] = *px;
+        }
+    }
+}
+
+/// The seven passes of an image, including any which are empty.
+pub fn passes(width: u32, height: u32) -> impl Iterator<Item = Pass> {
+    let span = |size: u32, start: u32, step: u32| (size + step - 1).saturating_sub(start) / step;
+
+    PASSES.into_iter().map(move |(x, y, dx, dy)| Pass {
+        x,
+        y,
+        dx,
+        dy,
+        width: span(width, x, dx),
+        height: span(height, y, dy),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dimensions(width: u32, height: u32) -> Vec<(u32, u32)> {
+        passes(width, height)
+            .map(|pass| (pass.width, pass.height))
+            .collect()
+    }
+
+    #[test]
+    fn test_pass_dimensions() {
+        assert_eq!(
+            dimensions(8, 8),
+            [(1, 1), (1, 1), (2, 1), (2, 2), (4, 2), (4, 4), (8, 4)]
+        );
+        assert_eq!(
+            dimensions(1, 1),
+            [(1, 1), (0, 1), (1, 0), (0, 1), (1, 0), (0, 1), (1, 0)]
+        );
+        assert_eq!(
+            dimensions(5, 3),
+            [(1, 1), (1, 1), (2, 0), (1, 1), (3, 1), (2, 2), (5, 1)]
+        );
+    }
+
+    #[test]
+    fn test_pass_order() {
+        let data: Vec<u32> = (0..64).collect();
+        let pixels: Vec<Vec<u32>> = passes(8, 8).map(|pass| pass.extract(&data, 8)).collect();
+
+        assert_eq!(pixels[0], [0]);
+        assert_eq!(pixels[1], [4]);
+        assert_eq!(pixels[2], [32, 36]);
+        assert_eq!(pixels[3], [2, 6, 34, 38]);
+        assert_eq!(pixels[4], [16, 18, 20, 22, 48, 50, 52, 54]);
+        assert_eq!(pixels[5].len(), 16);
+        assert_eq!(&pixels[6][..8], &(8..16).collect::<Vec<u32>>()[..]);
+        assert_eq!(pixels.iter().map(Vec::len).sum::<usize>(), 64);
+    }
+
+    #[test]
+    fn test_scatter() {
+        let data: Vec<u32> = (0..35).collect();
+        let mut out = vec![0; 35];
+
+        for pass in passes(7, 5) {
+            pass.scatter(&pass.extract(&data, 7), &mut out, 7);
+        }
+
+        assert_eq!(out, data);
+    }
+}
@@ -0,0 +1,601 @@
+use super::{
+    chunk::segment4, Chunk, ChunkSink, ChunkType, EncodeOptions, Header, ImageWriter, PixelFormat,
+    Png,
+};
+use crate::{calc, convert, err::*, img::Img, Color, Quad};
+use std::{fmt, str::FromStr};
+
+/// What happens to a frame's region once it has been shown.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum DisposeOp {
+    /// Leave the canvas as it is.
+    #[default]
+    None,
+    /// Clear the region to transparent black.
+    Background,
+    /// Restore the region to what it was before the frame was drawn.
+    Previous,
+}
+
+/// How a frame is drawn onto the canvas.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum BlendOp {
+    /// Replace the region, alpha included.
+    #[default]
+    Source,
+    /// Composite the frame over the region.
+    Over,
+}
+
+impl TryFrom<u8> for DisposeOp {
+    type Error = PngErr;
+    fn try_from(op: u8) -> PngRes<Self> {
+        Ok(match op {
+            0 => DisposeOp::None,
+            1 => DisposeOp::Background,
+            2 => DisposeOp::Previous,
+            _ => return Err(PngErr::InvalidAnimation),
+        })
+    }
+}
+
+impl TryFrom<u8> for BlendOp {
+    type Error = PngErr;
+    fn try_from(op: u8) -> PngRes<Self> {
+        Ok(match op {
+            0 => BlendOp::Source,
+            1 => BlendOp::Over,
+            _ => return Err(PngErr::InvalidAnimation),
+        })
+    }
+}
+
+impl fmt::Display for DisposeOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            DisposeOp::None => "none",
+            DisposeOp::Background => "background",
+            DisposeOp::Previous => "previous",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for BlendOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            BlendOp::Source => "source",
+            BlendOp::Over => "over",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// One frame of an animation, drawn at `x`, `y` on the canvas and shown
+/// for `delay_num / delay_den` seconds.
+#[derive(Debug, Clone)]
+pub struct Frame<P: Color = u32> {
+    pub img: Img<P>,
+    pub x: u32,
+    pub y: u32,
+    pub delay_num: u16,
+    /// A denominator of 0 is read as 100.
+    pub delay_den: u16,
+    pub dispose: DisposeOp,
+    pub blend: BlendOp,
+}
+
+impl<P: Color> Frame<P> {
+    /// A frame at the origin, shown for `delay` milliseconds.
+    pub fn new(img: Img<P>, delay: u16) -> Self {
+        Self {
+            img,
+            x: 0,
+            y: 0,
+            delay_num: delay,
+            delay_den: 1000,
+            dispose: DisposeOp::default(),
+            blend: BlendOp::default(),
+        }
+    }
+
+    /// The delay in seconds.
+    pub fn delay(&self) -> f64 {
+        let den = match self.delay_den {
+            0 => 100,
+            den => den,
+        };
+
+        f64::from(self.delay_num) / f64::from(den)
+    }
+
+    fn control(&self, sequence: u32) -> PngRes<Chunk> {
+        let mut data = Vec::with_capacity(26);
+        for int in [
+            sequence,
+            self.img.width(),
+            self.img.height(),
+            self.x,
+            self.y,
+        ] {
+            data.extend(int.to_be_bytes());
+        }
+
+        data.extend(self.delay_num.to_be_bytes());
+        data.extend(self.delay_den.to_be_bytes());
+        data.extend([self.dispose as u8, self.blend as u8]);
+
+        Ok(Chunk::new(ChunkType::from_str("fcTL")?, data))
+    }
+}
+
+/// Read a big endian integer from the start of `data`.
+fn be_u32(data: &[u8]) -> PngRes<u32> {
+    let bytes = data.get(0..4).ok_or(PngErr::InvalidAnimation)?;
+    Ok(u32::from_be_bytes(segment4(bytes)?))
+}
+
+/// The parsed contents of an fcTL chunk, waiting for its image data.
+struct Control {
+    width: u32,
+    height: u32,
+    x: u32,
+    y: u32,
+    delay_num: u16,
+    delay_den: u16,
+    dispose: DisposeOp,
+    blend: BlendOp,
+}
+
+impl Control {
+    fn from_chunk(chunk: &Chunk) -> PngRes<Self> {
+        let data = chunk.data();
+        PngErr::is_or(data.len() == 26, PngErr::InvalidAnimation)?;
+
+        let int = |at: usize| be_u32(&data[at..]);
+        let short = |at: usize| u16::from_be_bytes([data[at], data[at + 1]]);
+
+        let (width, height) = (int(4)?, int(8)?);
+        PngErr::not_or(width == 0 || height == 0, PngErr::InvalidAnimation)?;
+
+        Ok(Self {
+            width,
+            height,
+            x: int(12)?,
+            y: int(16)?,
+            delay_num: short(20),
+            delay_den: short(22),
+            dispose: DisposeOp::try_from(data[24])?,
+            blend: BlendOp::try_from(data[25])?,
+        })
+    }
+
+    fn decode<P: Color>(self, header: &Header, data: &[u8]) -> PngRes<Frame<P>> {
+        let (pixels, filter) = header.pixels(data, self.width, self.height)?;
+        let mut img = Img::from_parts(self.width, self.height, pixels, filter)?;
+        img.set_color_space(header.color_space.clone());
+
+        Ok(Frame {
+            img,
+            x: self.x,
+            y: self.y,
+            delay_num: self.delay_num,
+            delay_den: self.delay_den,
+            dispose: self.dispose,
+            blend: self.blend,
+        })
+    }
+}
+
+impl ChunkSink for Vec<Chunk> {
+    fn write_chunk(&mut self, chunk: Chunk) -> PngRes {
+        self.push(chunk);
+        Ok(())
+    }
+}
+
+/// An animated PNG: a canvas, the frames drawn onto it in turn, and
+/// optionally a still image for decoders which don't support APNG.
+#[derive(Debug, Clone)]
+pub struct Animation<P: Color = u32> {
+    pub width: u32,
+    pub height: u32,
+    /// Times to play the animation, or 0 to loop forever.
+    pub plays: u32,
+    /// Shown in place of the animation by decoders without APNG support.
+    /// When `None` the first frame is used.
+    pub default_image: Option<Img<P>>,
+    pub frames: Vec<Frame<P>>,
+}
+
+impl<P: Color> Animation<P> {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            plays: 0,
+            default_image: None,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Decode every frame of `png`, checking the sequence numbers.
+    pub fn from_png(png: &Png) -> PngRes<Self> {
+        let header = Header::from_chunks(png.chunks())?;
+        let actl = png
+            .chunk_by_type("acTL")
+            .map(Chunk::data)
+            .ok_or(PngErr::InvalidAnimation)?;
+        PngErr::is_or(actl.len() == 8, PngErr::InvalidAnimation)?;
+
+        let mut animation = Self::new(header.ihdr.width, header.ihdr.height);
+        animation.plays = be_u32(&actl[4..])?;
+
+        let mut next = 0;
+        let mut check = |data: &[u8]| {
+            PngErr::is_or(be_u32(data)? == next, PngErr::InvalidAnimation)?;
+            next += 1;
+            Ok::<_, PngErr>(())
+        };
+
+        // The frame being collected, and whether its data is in IDAT.
+        let mut current: Option<(Control, Vec<u8>, bool)> = None;
+        let mut default = Vec::new();
+
+        for chunk in png.chunks() {
+            match chunk.chunk_type().to_string().as_str() {
+                "fcTL" => {
+                    check(chunk.data())?;
+                    if let Some((control, data, _)) = current.take() {
+                        animation.frames.push(control.decode(&header, &data)?);
+                    }
+
+                    current = Some((Control::from_chunk(chunk)?, Vec::new(), false));
+                }
+                "IDAT" => match &mut current {
+                    Some((_, data, idat)) if animation.frames.is_empty() => {
+                        *idat = true;
+                        data.extend_from_slice(chunk.data());
+                    }
+                    None => default.extend_from_slice(chunk.data()),
+                    _ => return Err(PngErr::InvalidAnimation),
+                },
+                "fdAT" => {
+                    check(chunk.data())?;
+                    match &mut current {
+                        Some((_, data, false)) => data.extend_from_slice(&chunk.data()[4..]),
+                        _ => return Err(PngErr::InvalidAnimation),
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if let Some((control, data, _)) = current {
+            animation.frames.push(control.decode(&header, &data)?);
+        }
+
+        if !default.is_empty() {
+            let (width, height) = (animation.width, animation.height);
+            let (pixels, filter) = header.pixels(&default, width, height)?;
+            let mut img = Img::from_parts(width, height, pixels, filter)?;
+            img.set_color_space(header.color_space.clone());
+            animation.default_image = Some(img);
+        }
+
+        PngErr::is_or(
+            animation.frames.len() == convert!(usize; be_u32(actl)?)?,
+            PngErr::InvalidAnimation,
+        )?;
+        animation.check()?;
+        Ok(animation)
+    }
+
+    /// Check that there is at least one frame, that every frame lies on
+    /// the canvas, and that the first frame covers it.
+    fn check(&self) -> PngRes {
+        let first = self.frames.first().ok_or(PngErr::InvalidAnimation)?;
+        PngErr::is_or(
+            first.x == 0 && first.y == 0 && first.img.dimensions() == (self.width, self.height),
+            PngErr::InvalidAnimation,
+        )?;
+
+        for frame in &self.frames {
+            let right = frame.x.checked_add(frame.img.width());
+            let bottom = frame.y.checked_add(frame.img.height());
+            PngErr::is_or(
+                right.is_some_and(|right| right <= self.width)
+                    && bottom.is_some_and(|bottom| bottom <= self.height),
+                PngErr::InvalidAnimation,
+            )?;
+        }
+
+        if let Some(img) = &self.default_image {
+            PngErr::is_or(
+                img.dimensions() == (self.width, self.height),
+                PngErr::InvalidAnimation,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Encode the animation. Every frame shares one pixel format, chosen
+    /// from all of their pixels when `options.reduce` is set.
+    pub fn to_png_with(&self, options: &EncodeOptions) -> PngRes<Png> {
+        self.check()?;
+
+        let format = if options.reduce {
+            let pixels: Vec<P> = self
+                .default_image
+                .iter()
+                .chain(self.frames.iter().map(|frame| &frame.img))
+                .flat_map(|img| img.data().iter().copied())
+                .collect();
+            PixelFormat::analyse(&pixels, self.width)
+        } else {
+            PixelFormat::rgba::<P>()
+        };
+
+        let mut actl = convert!(u32; self.frames.len())?.to_be_bytes().to_vec();
+        actl.extend(self.plays.to_be_bytes());
+
+        let first = &self.frames[0];
+        let still = self.default_image.as_ref().unwrap_or(&first.img);
+
+        let mut header = vec![Chunk::new(ChunkType::from_str("acTL")?, actl)];
+        header.extend(still.color_space().chunks()?);
+
+        let mut sequence = 0;
+        let mut frames = self.frames.iter();
+        if self.default_image.is_none() {
+            header.push(first.control(sequence)?);
+            sequence += 1;
+            frames.next();
+        }
+
+        let mut chunks = encode(still, &format, options, header)?;
+        let iend = chunks.pop().ok_or(PngErr::InvalidAnimation)?;
+
+        for frame in frames {
+            chunks.push(frame.control(sequence)?);
+            sequence += 1;
+
+            let image = encode(&frame.img, &format, options, Vec::new())?;
+            for idat in image
+                .iter()
+                .filter(|chunk| chunk.chunk_type().to_string() == "IDAT")
+            {
+                let mut data = sequence.to_be_bytes().to_vec();
+                data.extend_from_slice(idat.data());
+                chunks.push(Chunk::new(ChunkType::from_str("fdAT")?, data));
+                sequence += 1;
+            }
+        }
+
+        chunks.push(iend);
+        Ok(Png::from_chunks(chunks))
+    }
+
+    /// Draw each frame in turn, returning the whole canvas as it is shown
+    /// after each one.
+    pub fn render(&self) -> PngRes<Vec<Img<P>>> {
+        self.check()?;
+
+        let mut canvas = Img::new(self.width, self.height);
+        let mut shown = Vec::with_capacity(self.frames.len());
+        let width = convert!(usize; self.width)?;
+
+        for frame in &self.frames {
+            let saved = (frame.dispose == DisposeOp::Previous).then(|| canvas.clone());
+            let (x, fw) = convert!(usize; frame.x, frame.img.width())?;
+            let y = convert!(usize; frame.y)?;
+
+            for (row, pixels) in frame.img.data().chunks(fw).enumerate() {
+                let start = calc!((y + row) * width + x);
+                let dest = &mut canvas.data_mut()[start..start + fw];
+
+                for (dest, &src) in dest.iter_mut().zip(pixels) {
+                    *dest = match frame.blend {
+                        BlendOp::Source => src,
+                        BlendOp::Over => over(src, *dest),
+                    };
+                }
+            }
+
+            shown.push(canvas.clone());
+
+            match (frame.dispose, saved) {
+                (DisposeOp::Previous, Some(saved)) => canvas = saved,
+                (DisposeOp::Background, _) => {
+                    for row in 0..convert!(usize; frame.img.height())? {
+                        let start = calc!((y + row) * width + x);
+                        canvas.data_mut()[start..start + fw].fill(P::default());
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(shown)
+    }
+}
+
+/// Encode one image with `format`, returning all of its chunks.
+fn encode<P: Color>(
+    img: &Img<P>,
+    format: &PixelFormat,
+    options: &EncodeOptions,
+    header: Vec<Chunk>,
+) -> PngRes<Vec<Chunk>> {
+    let (width, height) = img.dimensions();
+    let mut writer = ImageWriter::new(Vec::new(), width, height, format, options, header)?;
+    writer.write_img(img)?;
+    writer.finish()
+}
+
+/// Composite `src` over `dest` with straight alpha.
+fn over<P: Color>(src: P, dest: P) -> P {
+    let (src, dest) = (
+        src.to_rgba16().map(u64::from),
+        dest.to_rgba16().map(u64::from),
+    );
+    let max = u64::from(u16::MAX);
+
+    match src[3] {
+        0 => return P::from_rgba16(dest.map(|v| v as u16)),
+        a if a == max => return P::from_rgba16(src.map(|v| v as u16)),
+        _ => (),
+    }
+
+    let under = dest[3] * (max - src[3]) / max;
+    let alpha = src[3] + under;
+    let mut out = [0; 4];
+    for ((out, src_c), dest_c) in out.iter_mut().zip(&src[..3]).zip(&dest[..3]) {
+        *out = ((src_c * src[3] + dest_c * under) / alpha) as u16;
+    }
+
+    out[3] = alpha as u16;
+    P::from_rgba16(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, color: u32) -> Img {
+        Img::new_bg(width, height, color)
+    }
+
+    fn animation() -> Animation {
+        let mut animation = Animation::new(8, 6);
+        animation.plays = 3;
+        animation.frames = vec![
+            Frame::new(solid(8, 6, 0xFF0000FF), 100),
+            Frame {
+                x: 2,
+                y: 1,
+                dispose: DisposeOp::Background,
+                ..Frame::new(solid(4, 3, 0x00FF00FF), 250)
+            },
+            Frame {
+                x: 4,
+                y: 3,
+                blend: BlendOp::Over,
+                dispose: DisposeOp::Previous,
+                ..Frame::new(solid(4, 3, 0x0000FF80), 40)
+            },
+        ];
+
+        animation
+    }
+
+    fn names(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let original = animation();
+        let png = original.to_png_with(&EncodeOptions::new()).unwrap();
+
+        let names = names(&png);
+        assert_eq!(names[..3], ["IHDR", "acTL", "fcTL"]);
+        assert_eq!(names.last().unwrap(), "IEND");
+        assert_eq!(names.iter().filter(|name| *name == "fdAT").count(), 2);
+
+        let decoded: Animation =
+            Animation::from_png(&Png::try_from(&png.as_bytes()[..]).unwrap()).unwrap();
+        assert_eq!(decoded.plays, 3);
+        assert!(decoded.default_image.is_none());
+        for (frame, expected) in decoded.frames.iter().zip(&original.frames) {
+            assert_eq!(frame.img.data(), expected.img.data());
+            assert_eq!((frame.x, frame.y), (expected.x, expected.y));
+            assert_eq!(
+                (frame.delay_num, frame.delay_den),
+                (expected.delay_num, 1000)
+            );
+            assert_eq!(
+                (frame.dispose, frame.blend),
+                (expected.dispose, expected.blend)
+            );
+        }
+
+        // Decoders without APNG support see the first frame.
+        assert_eq!(png.to_img().unwrap().data(), original.frames[0].img.data());
+    }
+
+    #[test]
+    fn test_default_image() {
+        let mut original = animation();
+        original.default_image = Some(solid(8, 6, 0x123456FF));
+
+        let png = original.to_png_with(&EncodeOptions::new()).unwrap();
+        let names = names(&png);
+        let idat = names.iter().position(|name| name == "IDAT").unwrap();
+        assert!(idat < names.iter().position(|name| name == "fcTL").unwrap());
+
+        let decoded: Animation = Animation::from_png(&png).unwrap();
+        assert_eq!(decoded.frames.len(), 3);
+        assert_eq!(
+            decoded.default_image.unwrap().data(),
+            solid(8, 6, 0x123456FF).data()
+        );
+        assert_eq!(decoded.frames[0].img.data(), original.frames[0].img.data());
+    }
+
+    #[test]
+    fn test_render() {
+        let shown = animation().render().unwrap();
+        assert_eq!(shown.len(), 3);
+
+        let at = |img: &Img, x: usize, y: usize| img.data()[y * 8 + x];
+        assert_eq!(at(&shown[0], 3, 2), 0xFF0000FF);
+        assert_eq!(at(&shown[1], 3, 2), 0x00FF00FF);
+        assert_eq!(at(&shown[1], 1, 1), 0xFF0000FF);
+
+        // The second frame was cleared to transparent, then blended over.
+        assert_eq!(at(&shown[2], 3, 2), 0);
+        assert_eq!(at(&shown[2], 4, 3), 0x0000FF80);
+        assert_eq!(at(&shown[2], 7, 5), 0x7F0080FF);
+    }
+
+    #[test]
+    fn test_invalid_animations() {
+        let mut offset = animation();
+        offset.frames[0].x = 1;
+        assert!(offset.to_png_with(&EncodeOptions::new()).is_err());
+
+        let mut outside = animation();
+        outside.frames[1].x = 6;
+        assert!(outside.render().is_err());
+
+        let png = animation().to_png_with(&EncodeOptions::new()).unwrap();
+        let mut chunks = png.chunks().to_vec();
+        let last = chunks
+            .iter()
+            .rposition(|chunk| chunk.chunk_type().to_string() == "fdAT")
+            .unwrap();
+        chunks.swap(last, last - 1);
+        assert!(Animation::<u32>::from_png(&Png::from_chunks(chunks)).is_err());
+
+        let still = Png::from_img(solid(2, 2, 0xFF)).unwrap();
+        assert!(Animation::<u32>::from_png(&still).is_err());
+    }
+
+    #[test]
+    fn test_chunks_are_protected() {
+        let mut png = animation().to_png_with(&EncodeOptions::new()).unwrap();
+        assert!(png.encode("fcTL", "hello").is_err());
+        assert!(png.discard("fdAT").is_err());
+
+        png.encode("ruSt", "hello").unwrap();
+        png.scrub();
+        assert_eq!(Animation::<u32>::from_png(&png).unwrap().frames.len(), 3);
+    }
+}
@@ -1,11 +1,52 @@
-use super::chunk_type::ChunkType;
-use crate::{err::*, INT_MAX};
-use std::{fmt, str::FromStr};
+use super::{chunk_type::ChunkType, Message, Palette, Trns};
+use crate::{convert, err::*, INT_MAX};
+use std::{fmt, io::Write, str::FromStr};
 
 pub fn segment4(bytes: &[u8]) -> PngRes<[u8; 4]> {
     bytes.try_into().map_err(|_| PngErr::InvalidSegment)
 }
 
+/// Gives a borrowed view of a chunk, so a `Png` can hold either owned
+/// `Chunk`s or `ChunkRef`s into a buffer.
+pub trait AsChunkRef {
+    fn as_chunk_ref(&self) -> ChunkRef<'_>;
+
+    /// The data as text, without the marker in front of a hidden message.
+    fn data_as_string(&self) -> PngRes<String> {
+        match Message::parse(self.as_chunk_ref().data) {
+            Ok(Message::Plain(data)) => String::from_utf8(data).map_err(|_| PngErr::NotUTF8),
+            _ => Err(PngErr::NotUTF8),
+        }
+    }
+
+    fn write_to(&self, writer: &mut impl Write) -> PngRes {
+        let chunk = self.as_chunk_ref();
+        writer
+            .write_all(&chunk.length.to_be_bytes())
+            .and_then(|_| writer.write_all(&chunk.chunk_type.bytes()))
+            .and_then(|_| writer.write_all(chunk.data))
+            .and_then(|_| writer.write_all(&chunk.crc.to_be_bytes()))
+            .map_err(|_| PngErr::WriteError)
+    }
+
+    fn as_bytes(&self) -> Vec<u8> {
+        let chunk = self.as_chunk_ref();
+        [
+            &chunk.length.to_be_bytes()[..],
+            &chunk.chunk_type.bytes(),
+            chunk.data,
+            &chunk.crc.to_be_bytes(),
+        ]
+        .concat()
+    }
+}
+
+impl<T: AsChunkRef> AsChunkRef for &T {
+    fn as_chunk_ref(&self) -> ChunkRef<'_> {
+        (**self).as_chunk_ref()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Chunk {
     length: u32,
@@ -15,40 +56,27 @@ pub struct Chunk {
 }
 
 impl Chunk {
-    pub fn ihdr(width: u32, height: u32) -> PngRes<Self> {
-        PngErr::not_or(width > INT_MAX as u32, PngErr::IHDRWidthOverflow)?;
-        PngErr::not_or(height > INT_MAX as u32, PngErr::IHDRHeightOverflow)?;
+    pub fn iend() -> PngRes<Self> {
+        Ok(Self::new(ChunkType::from_str("IEND")?, Vec::new()))
+    }
 
+    pub fn idat(data: &[u8]) -> PngRes<Self> {
+        Ok(Self::new(ChunkType::from_str("IDAT")?, data.to_vec()))
+    }
+
+    pub fn plte(palette: &Palette) -> PngRes<Self> {
         Ok(Self::new(
-            ChunkType::from_str("IHDR")?,
-            width
-                .to_be_bytes()
+            ChunkType::from_str("PLTE")?,
+            palette
+                .colors()
                 .iter()
-                .chain(height.to_be_bytes().iter())
-                .chain([8, 6, 0, 0, 0].iter())
-                .copied()
+                .flat_map(|px| px.to_be_bytes().into_iter().take(3))
                 .collect(),
         ))
     }
 
-    pub fn ihdr_to_dimensions(chunk: &Chunk) -> PngRes<(u32, u32)> {
-        let header = chunk.data();
-        if header.len() == 13 {
-            Ok((
-                u32::from_be_bytes(segment4(&header[0..4])?),
-                u32::from_be_bytes(segment4(&header[4..8])?),
-            ))
-        } else {
-            Err(PngErr::InvalidIHDR)
-        }
-    }
-
-    pub fn iend() -> PngRes<Self> {
-        Ok(Self::new(ChunkType::from_str("IEND")?, Vec::new()))
-    }
-
-    pub fn idat(data: &[u8]) -> PngRes<Self> {
-        Ok(Self::new(ChunkType::from_str("IDAT")?, data.to_vec()))
+    pub fn trns(trns: &Trns) -> PngRes<Self> {
+        Ok(Self::new(ChunkType::from_str("tRNS")?, trns.data()))
     }
 
     pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Self {
@@ -56,15 +84,7 @@ impl Chunk {
             panic!("Data length exceeds specified maximum of 2^31 bytes.");
         }
 
-        let crc = crc::crc32::checksum_ieee(
-            chunk_type
-                .bytes()
-                .iter()
-                .chain(data.iter())
-                .copied()
-                .collect::<Vec<u8>>()
-                .as_slice(),
-        );
+        let crc = checksum(&chunk_type, &data);
 
         Self {
             length: data.len() as u32,
@@ -90,25 +110,108 @@ impl Chunk {
         self.crc
     }
 
-    pub fn data_as_string(&self) -> PngRes<String> {
-        String::from_utf8(self.data.to_vec()).map_err(|_| PngErr::NotUTF8)
+    /// Assemble a chunk from its parts as read from a stream, checking
+    /// the CRC against the type and data.
+    pub fn from_parts(chunk_type: ChunkType, data: Vec<u8>, crc: u32) -> PngRes<Self> {
+        PngErr::is_or(checksum(&chunk_type, &data) == crc, PngErr::CRCMismatch)?;
+
+        Ok(Self {
+            length: convert!(u32; data.len())?,
+            chunk_type,
+            data,
+            crc,
+        })
     }
+}
 
-    pub fn as_bytes(&self) -> Vec<u8> {
-        self.length
-            .to_be_bytes()
-            .iter()
-            .chain(self.chunk_type.bytes().iter())
-            .chain(self.data.iter())
-            .chain(self.crc.to_be_bytes().iter())
-            .copied()
-            .collect()
+impl AsChunkRef for Chunk {
+    fn as_chunk_ref(&self) -> ChunkRef<'_> {
+        ChunkRef {
+            length: self.length,
+            chunk_type: self.chunk_type,
+            data: &self.data,
+            crc: self.crc,
+        }
     }
 }
 
 impl TryFrom<&[u8]> for Chunk {
     type Error = PngErr;
     fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        ChunkRef::try_from(bytes).map(Chunk::from)
+    }
+}
+
+impl From<ChunkRef<'_>> for Chunk {
+    fn from(chunk: ChunkRef) -> Self {
+        let ChunkRef {
+            length,
+            chunk_type,
+            data,
+            crc,
+        } = chunk;
+
+        Self {
+            length,
+            chunk_type,
+            data: data.to_vec(),
+            crc,
+        }
+    }
+}
+
+impl fmt::Display for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_chunk_ref().fmt(f)
+    }
+}
+
+/// A chunk whose data is borrowed from the buffer it was parsed from.
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkRef<'a> {
+    length: u32,
+    chunk_type: ChunkType,
+    data: &'a [u8],
+    crc: u32,
+}
+
+impl<'a> ChunkRef<'a> {
+    /// Parse the chunk at the start of `bytes`, returning it along with
+    /// the bytes following it.
+    pub fn split_from(bytes: &'a [u8]) -> PngRes<(Self, &'a [u8])> {
+        PngErr::not_or(bytes.len() < 12, PngErr::ShortChunk)?;
+
+        let length = u32::from_be_bytes(segment4(&bytes[0..4])?);
+        PngErr::not_or(length > INT_MAX, PngErr::LengthMismatch)?;
+        let end = convert!(usize; length)? + 12;
+        PngErr::not_or(bytes.len() < end, PngErr::ShortChunk)?;
+
+        let (chunk, rest) = bytes.split_at(end);
+        Ok((Self::try_from(chunk)?, rest))
+    }
+
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+impl AsChunkRef for ChunkRef<'_> {
+    fn as_chunk_ref(&self) -> ChunkRef<'_> {
+        *self
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for ChunkRef<'a> {
+    type Error = PngErr;
+    fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
         PngErr::not_or(bytes.len() < 12, PngErr::ShortChunk)?;
 
         let crc_offset = bytes.len() - 4;
@@ -116,7 +219,7 @@ impl TryFrom<&[u8]> for Chunk {
         let length = u32::from_be_bytes(segment4(&bytes[0..4])?);
         let chunk_type = ChunkType::from_bytes(&bytes[4..8])?;
         let crc = u32::from_be_bytes(segment4(&bytes[crc_offset..])?);
-        let data = bytes[8..crc_offset].to_vec();
+        let data = &bytes[8..crc_offset];
 
         PngErr::is_or(data.len() == length as usize, PngErr::LengthMismatch)?;
         PngErr::is_or(
@@ -124,7 +227,7 @@ impl TryFrom<&[u8]> for Chunk {
             PngErr::CRCMismatch,
         )?;
 
-        Ok(Chunk {
+        Ok(ChunkRef {
             length,
             chunk_type,
             data,
@@ -133,7 +236,7 @@ impl TryFrom<&[u8]> for Chunk {
     }
 }
 
-impl fmt::Display for Chunk {
+impl fmt::Display for ChunkRef<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let Self {
             length, chunk_type, ..
@@ -152,55 +255,15 @@ impl fmt::Display for Chunk {
     }
 }
 
-#[derive(Debug)]
-pub struct ChunkIter<'a> {
-    cur: &'a [u8],
-    tainted: bool,
-}
-
-impl<'a> ChunkIter<'a> {
-    pub fn new(cur: &'a [u8]) -> Self {
-        Self {
-            cur,
-            tainted: false,
-        }
-    }
-}
-
-impl<'a> Iterator for ChunkIter<'a> {
-    type Item = PngRes<Chunk>;
-    fn next(&mut self) -> Option<PngRes<Chunk>> {
-        if self.tainted || self.cur.len() == 0 {
-            return None;
-        } else if self.cur.len() < 12 {
-            self.tainted = true;
-            return Some(Err(PngErr::ShortChunk));
-        }
-
-        let len = segment4(&self.cur[0..4]).unwrap();
-        let len = u32::from_be_bytes(len) as usize + 12;
-
-        if self.cur.len() < len {
-            self.tainted = true;
-            return Some(Err(PngErr::ShortChunk));
-        }
-
-        let chunk = &self.cur[0..len];
-        self.cur = &self.cur[len..];
-
-        Chunk::try_from(chunk).map_or_else(
-            |e| {
-                self.tainted = true;
-                Some(Err(e))
-            },
-            |chunk| Some(Ok(chunk)),
-        )
-    }
+/// The CRC of a chunk's type and data, computed without joining them.
+fn checksum(chunk_type: &ChunkType, data: &[u8]) -> u32 {
+    let crc = crc::crc32::checksum_ieee(&chunk_type.bytes());
+    crc::crc32::update(crc, &crc::crc32::IEEE_TABLE, data)
 }
 
 #[cfg(test)]
 mod iter_tests {
-    use super::*;
+    use crate::png::{AsChunkRef, ChunkReader};
 
     fn valid_chunk() -> Vec<u8> {
         let data_length: u32 = 11;
@@ -242,7 +305,7 @@ mod iter_tests {
             .collect();
 
         let ref_chunk = valid_chunk();
-        for chunk in ChunkIter::new(bytes.as_slice()) {
+        for chunk in ChunkReader::new(bytes.as_slice()) {
             assert!(chunk.is_ok());
             assert_eq!(chunk.unwrap().as_bytes().as_slice(), ref_chunk.as_slice());
         }
@@ -256,7 +319,7 @@ mod iter_tests {
             .collect();
 
         let ref_chunk = valid_chunk();
-        let mut iter = ChunkIter::new(bytes.as_slice());
+        let mut iter = ChunkReader::new(bytes.as_slice());
 
         let first = iter.next().unwrap();
         assert!(first.is_ok());
@@ -279,7 +342,7 @@ mod iter_tests {
             .chain(valid_chunk().into_iter())
             .collect();
 
-        let mut iter = ChunkIter::new(big_chunk.as_slice());
+        let mut iter = ChunkReader::new(big_chunk.as_slice());
 
         assert!(iter.next().unwrap().is_err());
         assert!(iter.next().is_none());
@@ -287,7 +350,7 @@ mod iter_tests {
 
     #[test]
     fn test_empty_buffer() {
-        assert!(ChunkIter::new(&[]).next().is_none());
+        assert!(ChunkReader::new(&[][..]).next().is_none());
     }
 }
 
@@ -409,4 +472,21 @@ mod tests {
 
         let _chunk_string = format!("{}", chunk);
     }
+
+    #[test]
+    fn test_chunk_ref() {
+        let mut bytes = testing_chunk().as_bytes();
+        bytes.extend_from_slice(b"rest");
+
+        let (chunk, rest) = ChunkRef::split_from(&bytes).unwrap();
+        assert_eq!(rest, b"rest");
+        assert_eq!(chunk.length(), 42);
+        assert_eq!(chunk.data().as_ptr(), bytes[8..].as_ptr());
+        assert_eq!(chunk.to_string(), testing_chunk().to_string());
+        assert_eq!(Chunk::from(chunk).as_bytes(), testing_chunk().as_bytes());
+
+        assert!(ChunkRef::split_from(&bytes[..20]).is_err());
+        bytes[0] = 0x80;
+        assert!(ChunkRef::split_from(&bytes).is_err());
+    }
 }
@@ -51,8 +51,15 @@ impl ChunkType {
         Ok(ChunkType { bytes })
     }
 
+    /// Whether this is one of the acTL, fcTL or fdAT chunks used by APNG.
+    pub fn is_animation(&self) -> bool {
+        matches!(&self.bytes, b"acTL" | b"fcTL" | b"fdAT")
+    }
+
     pub fn checked_me_type(&self) -> PngRes {
-        if self.is_critical() {
+        if self.is_animation() {
+            Err(PngErr::AnimationChunk)
+        } else if self.is_critical() {
             Err(PngErr::ExpectNonCritical)
         } else if self.is_public() {
             Err(PngErr::ExpectPrivate)
@@ -0,0 +1,448 @@
+use super::{chunk::segment4, Chunk, ChunkType};
+use crate::{err::*, fs, Color};
+use std::{fmt, str::FromStr};
+
+/// Image gamma from a gAMA chunk, multiplied by 100000.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Gamma(pub u32);
+
+impl Gamma {
+    /// The gamma recommended alongside an sRGB chunk.
+    pub const SRGB: Gamma = Gamma(45455);
+
+    pub fn value(self) -> f64 {
+        f64::from(self.0) / 100000.0
+    }
+}
+
+/// CIE 1931 xy coordinates of the white point and primaries from a cHRM
+/// chunk, each multiplied by 100000.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Chromaticities {
+    pub white: (u32, u32),
+    pub red: (u32, u32),
+    pub green: (u32, u32),
+    pub blue: (u32, u32),
+}
+
+impl Chromaticities {
+    /// The chromaticities recommended alongside an sRGB chunk.
+    pub const SRGB: Chromaticities = Chromaticities {
+        white: (31270, 32900),
+        red: (64000, 33000),
+        green: (30000, 60000),
+        blue: (15000, 6000),
+    };
+
+    fn points(&self) -> [(u32, u32); 4] {
+        [self.white, self.red, self.green, self.blue]
+    }
+
+    /// Matrix taking linear RGB in these primaries to CIE XYZ.
+    fn to_xyz(self) -> Matrix {
+        let xyz = |(x, y): (u32, u32)| {
+            let (x, y) = (f64::from(x), f64::from(y));
+            [x / y, 1.0, (100000.0 - x - y) / y]
+        };
+
+        let primaries = transpose([xyz(self.red), xyz(self.green), xyz(self.blue)]);
+        let scale = apply(&inverse(&primaries), xyz(self.white));
+        primaries.map(|row| [0, 1, 2].map(|i| row[i] * scale[i]))
+    }
+}
+
+/// The rendering intent from an sRGB chunk.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RenderingIntent {
+    Perceptual = 0,
+    RelativeColorimetric = 1,
+    Saturation = 2,
+    AbsoluteColorimetric = 3,
+}
+
+impl TryFrom<u8> for RenderingIntent {
+    type Error = PngErr;
+    fn try_from(byte: u8) -> PngRes<Self> {
+        Ok(match byte {
+            0 => RenderingIntent::Perceptual,
+            1 => RenderingIntent::RelativeColorimetric,
+            2 => RenderingIntent::Saturation,
+            3 => RenderingIntent::AbsoluteColorimetric,
+            _ => return Err(PngErr::InvalidColorSpace),
+        })
+    }
+}
+
+/// A named, uncompressed ICC profile from an iCCP chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IccProfile {
+    pub name: String,
+    pub profile: Vec<u8>,
+}
+
+impl IccProfile {
+    fn from_chunk(chunk: &Chunk) -> PngRes<Self> {
+        let data = chunk.data();
+        let nul = data
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or(PngErr::InvalidColorSpace)?;
+
+        PngErr::not_or(nul == 0 || nul > 79, PngErr::InvalidColorSpace)?;
+        PngErr::is_or(data.get(nul + 1) == Some(&0), PngErr::InvalidColorSpace)?;
+
+        Ok(Self {
+            name: data[..nul].iter().map(|&byte| char::from(byte)).collect(),
+            profile: fs::decompress(&data[nul + 2..])?,
+        })
+    }
+
+    fn to_chunk(&self) -> PngRes<Chunk> {
+        let name: Vec<u8> = self
+            .name
+            .chars()
+            .map(|ch| u8::try_from(ch).map_err(|_| PngErr::InvalidColorSpace))
+            .collect::<PngRes<_>>()?;
+
+        PngErr::not_or(
+            name.is_empty() || name.len() > 79 || name.contains(&0),
+            PngErr::InvalidColorSpace,
+        )?;
+
+        let mut data = name;
+        data.extend([0, 0]);
+        data.extend(fs::compress(&self.profile)?);
+        Ok(Chunk::new(ChunkType::from_str("iCCP")?, data))
+    }
+}
+
+/// The gAMA, cHRM, sRGB and iCCP chunks, which describe how the stored
+/// samples map to real colors.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ColorSpace {
+    pub gamma: Option<Gamma>,
+    pub chromaticities: Option<Chromaticities>,
+    pub rendering_intent: Option<RenderingIntent>,
+    pub icc_profile: Option<IccProfile>,
+}
+
+impl ColorSpace {
+    /// An sRGB color space, with the recommended gAMA and cHRM fallbacks.
+    pub fn srgb(intent: RenderingIntent) -> Self {
+        Self {
+            gamma: Some(Gamma::SRGB),
+            chromaticities: Some(Chromaticities::SRGB),
+            rendering_intent: Some(intent),
+            icc_profile: None,
+        }
+    }
+
+    pub fn from_chunks(chunks: &[Chunk]) -> PngRes<Self> {
+        let find = |name: &str| {
+            chunks
+                .iter()
+                .find(|chunk| chunk.chunk_type().to_string() == name)
+                .map(Chunk::data)
+        };
+
+        let gamma = match find("gAMA") {
+            Some(data) => Some(Gamma(u32::from_be_bytes(segment4(data)?))),
+            None => None,
+        };
+
+        let chromaticities = match find("cHRM") {
+            Some(data) if data.len() == 32 => {
+                let mut values = data.chunks(4).map(|value| {
+                    u32::from_be_bytes(value.try_into().expect("chunks(4) of 32 bytes"))
+                });
+                let mut point = || (values.next().unwrap_or(0), values.next().unwrap_or(0));
+
+                Some(Chromaticities {
+                    white: point(),
+                    red: point(),
+                    green: point(),
+                    blue: point(),
+                })
+            }
+            Some(_) => return Err(PngErr::InvalidColorSpace),
+            None => None,
+        };
+
+        let rendering_intent = match find("sRGB") {
+            Some(&[intent]) => Some(RenderingIntent::try_from(intent)?),
+            Some(_) => return Err(PngErr::InvalidColorSpace),
+            None => None,
+        };
+
+        let icc_profile = chunks
+            .iter()
+            .find(|chunk| chunk.chunk_type().to_string() == "iCCP")
+            .map(IccProfile::from_chunk)
+            .transpose()?;
+
+        Ok(Self {
+            gamma,
+            chromaticities,
+            rendering_intent,
+            icc_profile,
+        })
+    }
+
+    /// Chunks to write between IHDR and PLTE. An sRGB chunk and an iCCP
+    /// chunk may not appear together, so the profile is dropped if the
+    /// rendering intent is set.
+    pub fn chunks(&self) -> PngRes<Vec<Chunk>> {
+        let mut chunks = Vec::new();
+
+        if let Some(Gamma(gamma)) = self.gamma {
+            let data = gamma.to_be_bytes().to_vec();
+            chunks.push(Chunk::new(ChunkType::from_str("gAMA")?, data));
+        }
+
+        if let Some(chrm) = self.chromaticities {
+            let data = chrm
+                .points()
+                .iter()
+                .flat_map(|(x, y)| x.to_be_bytes().into_iter().chain(y.to_be_bytes()))
+                .collect();
+            chunks.push(Chunk::new(ChunkType::from_str("cHRM")?, data));
+        }
+
+        match (self.rendering_intent, &self.icc_profile) {
+            (Some(intent), _) => {
+                chunks.push(Chunk::new(ChunkType::from_str("sRGB")?, vec![intent as u8]))
+            }
+            (None, Some(profile)) => chunks.push(profile.to_chunk()?),
+            (None, None) => (),
+        }
+
+        Ok(chunks)
+    }
+
+    /// Convert `pixels` from this color space to sRGB, returning false if
+    /// they were left unchanged. Pixels already marked as sRGB, or with no
+    /// gAMA or cHRM chunk to describe them, are not touched. ICC profiles
+    /// are not applied, so the gAMA and cHRM fallbacks are used instead.
+    pub fn to_srgb<P: Color>(&self, pixels: &mut [P]) -> bool {
+        if self.rendering_intent.is_some()
+            || (self.gamma.is_none() && self.chromaticities.is_none())
+        {
+            return false;
+        }
+
+        let decode: Vec<f64> = match self.gamma {
+            Some(gamma) if gamma.0 > 0 => {
+                let exponent = 1.0 / gamma.value();
+                (0..=u16::MAX)
+                    .map(|v| (f64::from(v) / 65535.0).powf(exponent))
+                    .collect()
+            }
+            _ => (0..=u16::MAX)
+                .map(|v| srgb_to_linear(f64::from(v) / 65535.0))
+                .collect(),
+        };
+
+        let matrix = self
+            .chromaticities
+            .filter(|&chrm| chrm != Chromaticities::SRGB)
+            .map(|chrm| {
+                let to_srgb = inverse(&Chromaticities::SRGB.to_xyz());
+                let adapt = bradford(chrm.white, Chromaticities::SRGB.white);
+                multiply(&to_srgb, &multiply(&adapt, &chrm.to_xyz()))
+            });
+
+        for px in pixels.iter_mut() {
+            let [r, g, b, a] = px.to_rgba16();
+            let mut rgb = [r, g, b].map(|v| decode[usize::from(v)]);
+
+            if let Some(matrix) = &matrix {
+                rgb = apply(matrix, rgb);
+            }
+
+            let [r, g, b] =
+                rgb.map(|v| (linear_to_srgb(v.clamp(0.0, 1.0)) * 65535.0).round() as u16);
+            *px = P::from_rgba16([r, g, b, a]);
+        }
+
+        true
+    }
+}
+
+impl fmt::Display for ColorSpace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+
+        if let Some(intent) = self.rendering_intent {
+            parts.push(format!("sRGB ({:?})", intent));
+        }
+
+        if let Some(profile) = &self.icc_profile {
+            parts.push(format!("ICC profile \"{}\"", profile.name));
+        }
+
+        if let Some(gamma) = self.gamma {
+            parts.push(format!("gamma {}", gamma.value()));
+        }
+
+        if self.chromaticities.is_some() {
+            parts.push(String::from("chromaticities"));
+        }
+
+        match parts.is_empty() {
+            true => write!(f, "unspecified"),
+            false => write!(f, "{}", parts.join(", ")),
+        }
+    }
+}
+
+fn srgb_to_linear(v: f64) -> f64 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(v: f64) -> f64 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+type Matrix = [[f64; 3]; 3];
+
+/// Bradford chromatic adaptation from one white point to another.
+fn bradford(from: (u32, u32), to: (u32, u32)) -> Matrix {
+    const CONE: Matrix = [
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296],
+    ];
+
+    let cone = |(x, y): (u32, u32)| {
+        let (x, y) = (f64::from(x), f64::from(y));
+        apply(&CONE, [x / y, 1.0, (100000.0 - x - y) / y])
+    };
+
+    let (src, dst) = (cone(from), cone(to));
+    let scale = [0, 1, 2].map(|i| {
+        let mut row = [0.0; 3];
+        row[i] = dst[i] / src[i];
+        row
+    });
+
+    multiply(&inverse(&CONE), &multiply(&scale, &CONE))
+}
+
+fn apply(m: &Matrix, v: [f64; 3]) -> [f64; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    a.map(|row| [0, 1, 2].map(|j| row[0] * b[0][j] + row[1] * b[1][j] + row[2] * b[2][j]))
+}
+
+fn transpose(m: Matrix) -> Matrix {
+    [0, 1, 2].map(|i| [m[0][i], m[1][i], m[2][i]])
+}
+
+fn inverse(m: &Matrix) -> Matrix {
+    let cofactor = |r: usize, c: usize| {
+        let (r1, r2, c1, c2) = ((r + 1) % 3, (r + 2) % 3, (c + 1) % 3, (c + 2) % 3);
+        m[r1][c1] * m[r2][c2] - m[r1][c2] * m[r2][c1]
+    };
+
+    let det: f64 = (0..3).map(|c| m[0][c] * cofactor(0, c)).sum();
+    [0, 1, 2].map(|r| [0, 1, 2].map(|c| cofactor(c, r) / det))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_color_space_round_trip() {
+        let mut color_space = ColorSpace::srgb(RenderingIntent::Saturation);
+        let chunks = color_space.chunks().unwrap();
+        let names: Vec<String> = chunks.iter().map(|c| c.chunk_type().to_string()).collect();
+
+        assert_eq!(names, ["gAMA", "cHRM", "sRGB"]);
+        assert_eq!(chunks[0].data(), [0, 0, 0xB1, 0x8F]);
+        assert_eq!(chunks[2].data(), [2]);
+        assert_eq!(ColorSpace::from_chunks(&chunks).unwrap(), color_space);
+
+        color_space.rendering_intent = None;
+        color_space.icc_profile = Some(IccProfile {
+            name: String::from("Display P3"),
+            profile: vec![1, 2, 3, 4],
+        });
+
+        let chunks = color_space.chunks().unwrap();
+        assert_eq!(&chunks[2].data()[..12], b"Display P3\0\0");
+        assert_eq!(ColorSpace::from_chunks(&chunks).unwrap(), color_space);
+    }
+
+    #[test]
+    fn test_invalid_color_space() {
+        assert!(ColorSpace::from_chunks(&[chunk("gAMA", &[0, 1])]).is_err());
+        assert!(ColorSpace::from_chunks(&[chunk("cHRM", &[0; 31])]).is_err());
+        assert!(ColorSpace::from_chunks(&[chunk("sRGB", &[4])]).is_err());
+        assert!(ColorSpace::from_chunks(&[chunk("iCCP", b"\0\0")]).is_err());
+        assert!(ColorSpace::from_chunks(&[chunk("iCCP", b"name\0\x01")]).is_err());
+    }
+
+    #[test]
+    fn test_inverse() {
+        let m = [[2.0, 0.0, 1.0], [1.0, 3.0, 0.0], [0.0, 1.0, 4.0]];
+        let identity = multiply(&m, &inverse(&m));
+
+        for (r, row) in identity.iter().enumerate() {
+            for (c, v) in row.iter().enumerate() {
+                assert!((v - f64::from(u8::from(r == c))).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn test_srgb_is_unchanged() {
+        let mut pixels = vec![0x123456FFu32, 0x80808080];
+        assert!(!ColorSpace::srgb(RenderingIntent::Perceptual).to_srgb(&mut pixels));
+        assert!(!ColorSpace::default().to_srgb(&mut pixels));
+        assert_eq!(pixels, [0x123456FF, 0x80808080]);
+    }
+
+    #[test]
+    fn test_linear_gamma_to_srgb() {
+        let linear = ColorSpace {
+            gamma: Some(Gamma(100000)),
+            ..ColorSpace::default()
+        };
+
+        let mut pixels = vec![0x000000FFu32, 0x808080FF, 0x40FFFF20];
+        assert!(linear.to_srgb(&mut pixels));
+        assert_eq!(pixels, [0x000000FF, 0xBCBCBCFF, 0x89FFFF20]);
+    }
+
+    #[test]
+    fn test_chromaticities_to_srgb() {
+        // Red and green primaries are swapped, so the channels swap too.
+        let swapped = ColorSpace {
+            chromaticities: Some(Chromaticities {
+                red: Chromaticities::SRGB.green,
+                green: Chromaticities::SRGB.red,
+                ..Chromaticities::SRGB
+            }),
+            ..ColorSpace::default()
+        };
+
+        let mut pixels = vec![0xFF0000FFu32, 0x00FF00FF, 0xFFFFFFFF];
+        assert!(swapped.to_srgb(&mut pixels));
+        assert_eq!(pixels, [0x00FF00FF, 0xFF0000FF, 0xFFFFFFFF]);
+    }
+}
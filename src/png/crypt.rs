@@ -0,0 +1,445 @@
+use crate::{convert, err::*};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use hkdf::Hkdf;
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::{fmt, str::FromStr};
+use x25519_dalek::{PublicKey, SharedSecret, StaticSecret};
+
+/// Where the byte saying how the message key was made is stored.
+const MODE: usize = 0;
+
+/// The key is derived from a passphrase.
+const PASSPHRASE: u8 = 0;
+/// The key is random and wrapped for each of a list of X25519 public keys.
+const RECIPIENTS: u8 = 1;
+
+const SALT: usize = 16;
+const CHECK: usize = 16;
+const DIGEST: usize = 16;
+const NONCE: usize = 12;
+const KEY: usize = 32;
+const TAG: usize = 16;
+
+/// A message key wrapped for one recipient, with its tag.
+const WRAPPED: usize = KEY + TAG;
+
+/// The mode, the Argon2 memory, passes and lanes as big endian
+/// u32s, then the salt, key check, a digest of all of those and the nonce.
+const HEADER: usize = MODE + 1 + 12 + SALT + CHECK + DIGEST + NONCE;
+
+/// The largest Argon2 costs a message may ask for, so that a crafted
+/// header can't make decrypting it exhaust memory or run for hours.
+/// `encrypt` asks for 19 MiB, 2 passes and 1 lane.
+const MAX_MEMORY: u32 = 1 << 18;
+const MAX_PASSES: u32 = 8;
+const MAX_LANES: u32 = 4;
+
+/// Whether the message was encrypted to public keys rather than with a
+/// passphrase, so needs an `Identity` to decrypt.
+pub fn is_for_recipients(data: &[u8]) -> bool {
+    data.get(MODE) == Some(&RECIPIENTS)
+}
+
+/// Encrypt `message` with a key derived from `passphrase` by Argon2id,
+/// using ChaCha20-Poly1305. The salt, nonce and Argon2 costs are stored
+/// in front of the ciphertext, so only the passphrase is needed to
+/// decrypt it.
+pub fn encrypt(message: &[u8], passphrase: &[u8]) -> PngRes<Vec<u8>> {
+    encrypt_with(message, passphrase, Params::default())
+}
+
+fn encrypt_with(message: &[u8], passphrase: &[u8], params: Params) -> PngRes<Vec<u8>> {
+    let mut salt = [0; SALT];
+    let mut nonce = [0; NONCE];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+
+    let (key, check) = derive(passphrase, &salt, &params)?;
+    let mut header = [
+        &[PASSPHRASE][..],
+        &params.m_cost().to_be_bytes(),
+        &params.t_cost().to_be_bytes(),
+        &params.p_cost().to_be_bytes(),
+        &salt,
+        &check,
+    ]
+    .concat();
+    header.extend(digest(&header));
+    header.extend(nonce);
+
+    seal(&key, message, header)
+}
+
+/// Decrypt data written by `encrypt`. A header that doesn't match its
+/// digest or a ciphertext that has been altered since is
+/// `TamperedMessage`, while a passphrase that doesn't match the stored key
+/// check is `WrongKey`. The digest is checked before Argon2 runs, so a
+/// damaged salt or cost is never blamed on the passphrase.
+pub fn decrypt(data: &[u8], passphrase: &[u8]) -> PngRes<Vec<u8>> {
+    PngErr::is_or(
+        data.len() >= HEADER && data[MODE] == PASSPHRASE,
+        PngErr::InvalidCipher,
+    )?;
+
+    let (header, ciphertext) = data.split_at(HEADER);
+    let (fields, stored) = header[..HEADER - NONCE].split_at(HEADER - NONCE - DIGEST);
+    PngErr::is_or(digest(fields) == stored, PngErr::TamperedMessage)?;
+
+    let field = |at: usize| {
+        u32::from_be_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]])
+    };
+    let (memory, passes, lanes) = (field(MODE + 1), field(MODE + 5), field(MODE + 9));
+    PngErr::is_or(
+        memory <= MAX_MEMORY && passes <= MAX_PASSES && lanes <= MAX_LANES,
+        PngErr::InvalidCipher,
+    )?;
+    let params = Params::new(memory, passes, lanes, None).map_err(|_| PngErr::InvalidCipher)?;
+
+    let salt = &fields[MODE + 13..MODE + 13 + SALT];
+    let stored = &fields[MODE + 13 + SALT..];
+
+    let (key, check) = derive(passphrase, salt, &params)?;
+    PngErr::is_or(check == stored, PngErr::WrongKey)?;
+    open(&key, ciphertext, header)
+}
+
+/// Encrypt `message` so that the holder of any one of the recipients'
+/// private keys can decrypt it. A random message key is wrapped for each
+/// recipient with a key agreed between them and a one-off X25519 key,
+/// whose public half is stored first. The wrapped keys don't say who they
+/// are for.
+pub fn encrypt_to(message: &[u8], recipients: &[Recipient]) -> PngRes<Vec<u8>> {
+    PngErr::not_or(recipients.is_empty(), PngErr::InvalidKey)?;
+
+    let mut key = Key::default();
+    let mut nonce = [0; NONCE];
+    OsRng.fill_bytes(&mut key);
+    OsRng.fill_bytes(&mut nonce);
+
+    let ephemeral = StaticSecret::random_from_rng(OsRng);
+    let public = PublicKey::from(&ephemeral);
+    let mut header = [
+        &[RECIPIENTS][..],
+        public.as_bytes(),
+        &convert!(u32; recipients.len())?.to_be_bytes(),
+    ]
+    .concat();
+
+    for Recipient(recipient) in recipients {
+        let wrap = wrap_key(ephemeral.diffie_hellman(recipient), &public, recipient)?;
+        let wrapped = wrap
+            .encrypt(&Nonce::default(), key.as_slice())
+            .map_err(|_| PngErr::InvalidCipher)?;
+        header.extend(wrapped);
+    }
+    header.extend(nonce);
+
+    seal(&key, message, header)
+}
+
+/// Decrypt data written by `encrypt_to`. If none of the wrapped keys are
+/// for `identity` it is `WrongKey`, and if the message itself was altered
+/// it is `TamperedMessage`.
+pub fn decrypt_for(data: &[u8], identity: &Identity) -> PngRes<Vec<u8>> {
+    let keys = MODE + 1 + KEY + 4;
+    PngErr::is_or(
+        is_for_recipients(data) && data.len() >= keys,
+        PngErr::InvalidCipher,
+    )?;
+
+    let mut ephemeral = [0; KEY];
+    ephemeral.copy_from_slice(&data[MODE + 1..MODE + 1 + KEY]);
+    let ephemeral = PublicKey::from(ephemeral);
+
+    let count = &data[keys - 4..keys];
+    let count = convert!(usize; u32::from_be_bytes([count[0], count[1], count[2], count[3]]))?;
+    let end = count
+        .checked_mul(WRAPPED)
+        .and_then(|wrapped| wrapped.checked_add(keys + NONCE))
+        .filter(|&end| end <= data.len())
+        .ok_or(PngErr::InvalidCipher)?;
+
+    let (header, ciphertext) = data.split_at(end);
+    let Recipient(public) = identity.recipient();
+    let wrap = wrap_key(identity.0.diffie_hellman(&ephemeral), &ephemeral, &public)
+        .map_err(|_| PngErr::InvalidCipher)?;
+
+    let key = header[keys..end - NONCE]
+        .chunks(WRAPPED)
+        .find_map(|wrapped| wrap.decrypt(&Nonce::default(), wrapped).ok())
+        .ok_or(PngErr::WrongKey)?;
+    open(Key::from_slice(&key), ciphertext, header)
+}
+
+/// Encrypt `message` with ChaCha20-Poly1305, authenticating `header` too,
+/// and append it to the header. The header must end with the nonce.
+fn seal(key: &Key, message: &[u8], header: Vec<u8>) -> PngRes<Vec<u8>> {
+    let nonce = Nonce::from_slice(&header[header.len() - NONCE..]);
+    let ciphertext = ChaCha20Poly1305::new(key)
+        .encrypt(
+            nonce,
+            Payload {
+                msg: message,
+                aad: &header,
+            },
+        )
+        .map_err(|_| PngErr::InvalidCipher)?;
+
+    Ok([header, ciphertext].concat())
+}
+
+fn open(key: &Key, ciphertext: &[u8], header: &[u8]) -> PngRes<Vec<u8>> {
+    let nonce = Nonce::from_slice(&header[header.len() - NONCE..]);
+    ChaCha20Poly1305::new(key)
+        .decrypt(
+            nonce,
+            Payload {
+                msg: ciphertext,
+                aad: header,
+            },
+        )
+        .map_err(|_| PngErr::TamperedMessage)
+}
+
+/// The cipher key and, from the same Argon2 output, a check value that
+/// tells a wrong passphrase apart from a damaged message.
+fn derive(passphrase: &[u8], salt: &[u8], params: &Params) -> PngRes<(Key, [u8; CHECK])> {
+    let mut output = [0; KEY + CHECK];
+    let params = Params::new(
+        params.m_cost(),
+        params.t_cost(),
+        params.p_cost(),
+        Some(output.len()),
+    )
+    .map_err(|_| PngErr::InvalidCipher)?;
+
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase, salt, &mut output)
+        .map_err(|_| PngErr::InvalidCipher)?;
+
+    let mut check = [0; CHECK];
+    check.copy_from_slice(&output[KEY..]);
+    Ok((*Key::from_slice(&output[..KEY]), check))
+}
+
+/// An unkeyed digest of the header fields, which catches damage to them
+/// without needing the passphrase.
+fn digest(fields: &[u8]) -> [u8; DIGEST] {
+    let mut digest = [0; DIGEST];
+    digest.copy_from_slice(&Sha256::digest(fields)[..DIGEST]);
+    digest
+}
+
+/// The key that wraps the message key for one recipient, bound to both
+/// public keys so it is never reused.
+fn wrap_key(
+    shared: SharedSecret,
+    ephemeral: &PublicKey,
+    recipient: &PublicKey,
+) -> PngRes<ChaCha20Poly1305> {
+    PngErr::is_or(shared.was_contributory(), PngErr::InvalidKey)?;
+
+    let salt = [&ephemeral.as_bytes()[..], recipient.as_bytes()].concat();
+    let mut key = Key::default();
+    Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes())
+        .expand(b"pngme x25519", &mut key)
+        .map_err(|_| PngErr::InvalidCipher)?;
+
+    Ok(ChaCha20Poly1305::new(&key))
+}
+
+/// An X25519 private key, which can decrypt messages encrypted to its
+/// `Recipient`. Written and parsed as hex.
+pub struct Identity(StaticSecret);
+
+impl Identity {
+    pub fn generate() -> Self {
+        Self(StaticSecret::random_from_rng(OsRng))
+    }
+
+    pub fn recipient(&self) -> Recipient {
+        Recipient(PublicKey::from(&self.0))
+    }
+}
+
+impl FromStr for Identity {
+    type Err = PngErr;
+    fn from_str(key: &str) -> PngRes<Self> {
+        Ok(Self(StaticSecret::from(from_hex(key)?)))
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", to_hex(self.0.as_bytes()))
+    }
+}
+
+/// An X25519 public key messages can be encrypted to. Written and parsed
+/// as hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recipient(PublicKey);
+
+impl FromStr for Recipient {
+    type Err = PngErr;
+    fn from_str(key: &str) -> PngRes<Self> {
+        Ok(Self(PublicKey::from(from_hex(key)?)))
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", to_hex(self.0.as_bytes()))
+    }
+}
+
+pub(super) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(super) fn from_hex(text: &str) -> PngRes<[u8; KEY]> {
+    let text = text.trim();
+    PngErr::is_or(text.len() == KEY * 2 && text.is_ascii(), PngErr::InvalidKey)?;
+
+    let mut bytes = [0; KEY];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[index * 2..index * 2 + 2], 16)
+            .map_err(|_| PngErr::InvalidKey)?;
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encrypt(message: &[u8], passphrase: &[u8]) -> Vec<u8> {
+        let cheap = Params::new(64, 1, 1, None).unwrap();
+        encrypt_with(message, passphrase, cheap).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let sealed = encrypt(b"This is where your secret message will be!", b"hunter2");
+
+        assert_eq!(
+            decrypt(&sealed, b"hunter2").unwrap(),
+            b"This is where your secret message will be!"
+        );
+        assert_ne!(
+            sealed,
+            encrypt(b"This is where your secret message will be!", b"hunter2")
+        );
+
+        let empty = encrypt(b"", b"hunter2");
+        assert_eq!(decrypt(&empty, b"hunter2").unwrap(), b"");
+    }
+
+    #[test]
+    fn test_wrong_key_and_tampering() {
+        let sealed = encrypt(b"secret", b"hunter2");
+        assert_eq!(decrypt(&sealed, b"hunter3"), Err(PngErr::WrongKey));
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(decrypt(&tampered, b"hunter2"), Err(PngErr::TamperedMessage));
+
+        let mut nonce = sealed.clone();
+        nonce[HEADER - 1] ^= 1;
+        assert_eq!(decrypt(&nonce, b"hunter2"), Err(PngErr::TamperedMessage));
+
+        assert_eq!(
+            decrypt(&sealed[..HEADER - 1], b"hunter2"),
+            Err(PngErr::InvalidCipher)
+        );
+
+        for at in [MODE + 1, MODE + 13, HEADER - NONCE - DIGEST - 1] {
+            let mut header = sealed.clone();
+            header[at] ^= 1;
+            assert_eq!(decrypt(&header, b"hunter2"), Err(PngErr::TamperedMessage));
+        }
+    }
+
+    #[test]
+    fn test_cost_limits() {
+        let sealed = encrypt(b"secret", b"hunter2");
+        let with_cost = |at: usize, cost: u32| {
+            let mut costly = sealed.clone();
+            costly[at..at + 4].copy_from_slice(&cost.to_be_bytes());
+            let fields = HEADER - NONCE - DIGEST;
+            let rehashed = digest(&costly[..fields]);
+            costly[fields..fields + DIGEST].copy_from_slice(&rehashed);
+            costly
+        };
+
+        // Argon2 running would end in `WrongKey`, so `InvalidCipher` means
+        // the costs were refused first.
+        for (at, max) in [
+            (MODE + 1, MAX_MEMORY),
+            (MODE + 5, MAX_PASSES),
+            (MODE + 9, MAX_LANES),
+        ] {
+            for cost in [max + 1, u32::MAX] {
+                let costly = with_cost(at, cost);
+                assert_eq!(decrypt(&costly, b"hunter3"), Err(PngErr::InvalidCipher));
+            }
+        }
+
+        for (at, max) in [(MODE + 5, MAX_PASSES), (MODE + 9, MAX_LANES)] {
+            let costly = with_cost(at, max);
+            assert_eq!(decrypt(&costly, b"hunter3"), Err(PngErr::WrongKey));
+        }
+    }
+
+    #[test]
+    fn test_recipients() {
+        let (alice, bob, eve) = (
+            Identity::generate(),
+            Identity::generate(),
+            Identity::generate(),
+        );
+        let sealed = encrypt_to(b"handoff", &[alice.recipient(), bob.recipient()]).unwrap();
+
+        assert!(is_for_recipients(&sealed));
+        assert!(!is_for_recipients(&encrypt(b"handoff", b"hunter2")));
+        assert_eq!(decrypt_for(&sealed, &alice).unwrap(), b"handoff");
+        assert_eq!(decrypt_for(&sealed, &bob).unwrap(), b"handoff");
+        assert_eq!(decrypt_for(&sealed, &eve), Err(PngErr::WrongKey));
+        assert_eq!(decrypt(&sealed, b"hunter2"), Err(PngErr::InvalidCipher));
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(decrypt_for(&tampered, &bob), Err(PngErr::TamperedMessage));
+
+        let mut count = sealed;
+        count[MODE + 1 + KEY..MODE + 5 + KEY].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(decrypt_for(&count, &alice), Err(PngErr::InvalidCipher));
+
+        assert_eq!(encrypt_to(b"handoff", &[]), Err(PngErr::InvalidKey));
+    }
+
+    #[test]
+    fn test_keys() {
+        let identity = Identity::generate();
+        let recipient = identity.recipient();
+
+        let parsed: Identity = identity.to_string().parse().unwrap();
+        assert_eq!(parsed.recipient(), recipient);
+        assert_eq!(
+            format!("{}\n", recipient).parse::<Recipient>(),
+            Ok(recipient)
+        );
+        assert_eq!(recipient.to_string().len(), 64);
+
+        assert_eq!(Recipient::from_str("abc"), Err(PngErr::InvalidKey));
+        assert_eq!(
+            Recipient::from_str(&"g".repeat(64)),
+            Err(PngErr::InvalidKey)
+        );
+    }
+}
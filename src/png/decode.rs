@@ -0,0 +1,380 @@
+use super::{adam7, Chunk, ColorSpace, ColorType, Filter, Ihdr, Palette, Trns};
+use crate::{area, calc, convert, err::*, fs, Color};
+
+/// The IHDR together with the PLTE and tRNS chunks, which between them
+/// describe how pixels are stored in the image data, and the color space
+/// chunks describing what those pixels mean.
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub ihdr: Ihdr,
+    pub palette: Option<Palette>,
+    pub trns: Option<Trns>,
+    pub color_space: ColorSpace,
+}
+
+impl Header {
+    pub fn from_chunks(chunks: &[Chunk]) -> PngRes<Self> {
+        let find = |name: &str| {
+            chunks
+                .iter()
+                .find(|chunk| chunk.chunk_type().to_string() == name)
+        };
+
+        let ihdr = Ihdr::try_from(find("IHDR").ok_or(PngErr::InvalidHeader)?)?;
+        let (plte, trns) = (find("PLTE"), find("tRNS"));
+
+        let palette = match (ihdr.color_type, plte) {
+            (ColorType::Indexed, None) => return Err(PngErr::InvalidPalette),
+            (ColorType::Indexed, Some(plte)) => Some(Palette::from_chunks(plte, trns)?),
+            _ => None,
+        };
+
+        let trns = match (ihdr.color_type, trns) {
+            (ColorType::Grayscale | ColorType::Rgb, Some(trns)) => {
+                Some(Trns::from_chunk(trns, ihdr.color_type)?)
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            ihdr,
+            palette,
+            trns,
+            color_space: ColorSpace::from_chunks(chunks)?,
+        })
+    }
+
+    /// Decompress an image `width` by `height` pixels, undoing the filters
+    /// and any interlacing. Rows of interlaced images report no filter.
+    pub fn pixels<P: Color>(
+        &self,
+        zlib: &[u8],
+        width: u32,
+        height: u32,
+    ) -> PngRes<(Vec<P>, Vec<u8>)> {
+        let mut data = fs::decompress(zlib)?;
+        if self.ihdr.interlace == 0 {
+            return self.scanlines(&mut data, width, height);
+        }
+
+        let mut pixels = vec![P::default(); area!(width, height)];
+        let mut offset = 0;
+
+        for pass in adam7::passes(width, height).filter(|pass| !pass.is_empty()) {
+            let stride = self.ihdr.stride(pass.width)?;
+            let len = calc!(stride + 1 * (convert!(usize; pass.height)?));
+            let data = data.get_mut(offset..).ok_or(PngErr::DataLengthMismatch)?;
+            let (sub, _) = self.scanlines(data, pass.width, pass.height)?;
+
+            pass.scatter(&sub, &mut pixels, width);
+            offset += len;
+        }
+
+        Ok((pixels, vec![0; convert!(usize; height)?]))
+    }
+
+    /// Unfilter `height` scanlines of an image `width` pixels wide and
+    /// expand them to RGBA pixels, returning the pixels and each row's
+    /// filter type.
+    pub fn scanlines<P: Color>(
+        &self,
+        data: &mut [u8],
+        width: u32,
+        height: u32,
+    ) -> PngRes<(Vec<P>, Vec<u8>)> {
+        let stride = self.ihdr.stride(width)?;
+        let rows = convert!(usize; height)?;
+
+        let len = stride
+            .checked_add(1)
+            .and_then(|scan| scan.checked_mul(rows))
+            .ok_or(PngErr::IntOverflow)?;
+        PngErr::not_or(data.len() < len, PngErr::DataLengthMismatch)?;
+
+        let bpp = self.ihdr.bpp();
+        let mut prev = vec![0u8; stride];
+        let mut pixels = Vec::with_capacity(area!(width, height));
+        let mut filter = Vec::with_capacity(rows);
+
+        for scan in data[..len].chunks_mut(stride + 1) {
+            let (ft, row) = scan.split_at_mut(1);
+            Filter::try_from(ft[0])?.unfilter(bpp, &prev, row);
+            self.expand(row, width, &mut pixels)?;
+            filter.push(ft[0]);
+            prev.copy_from_slice(row);
+        }
+
+        Ok((pixels, filter))
+    }
+
+    /// Expand one unfiltered scanline into RGBA pixels.
+    fn expand<P: Color>(&self, row: &[u8], width: u32, out: &mut Vec<P>) -> PngRes {
+        let depth = self.ihdr.bit_depth;
+        let mut samples = Samples::new(row, depth);
+        let mut next = || samples.next().ok_or(PngErr::DataLengthMismatch);
+
+        for _ in 0..width {
+            let rgba = match self.ihdr.color_type {
+                ColorType::Grayscale => {
+                    let sample = next()?;
+                    let v = scale(sample, depth);
+                    match self.trns {
+                        Some(Trns::Gray(key)) if key == sample => [v, v, v, 0],
+                        _ => [v, v, v, u16::MAX],
+                    }
+                }
+                ColorType::GrayscaleAlpha => {
+                    let (v, a) = (scale(next()?, depth), scale(next()?, depth));
+                    [v, v, v, a]
+                }
+                ColorType::Rgb => {
+                    let (r, g, b) = (next()?, next()?, next()?);
+                    let alpha = match self.trns {
+                        Some(Trns::Rgb(kr, kg, kb)) if (kr, kg, kb) == (r, g, b) => 0,
+                        _ => u16::MAX,
+                    };
+                    [scale(r, depth), scale(g, depth), scale(b, depth), alpha]
+                }
+                ColorType::Rgba => {
+                    let (r, g, b, a) = (next()?, next()?, next()?, next()?);
+                    [r, g, b, a].map(|sample| scale(sample, depth))
+                }
+                ColorType::Indexed => {
+                    let idx = usize::from(next()?);
+                    self.palette
+                        .as_ref()
+                        .and_then(|palette| palette.get(idx))
+                        .ok_or(PngErr::InvalidPalette)?
+                        .to_rgba16()
+                }
+            };
+
+            out.push(P::from_rgba16(rgba));
+        }
+
+        Ok(())
+    }
+}
+
+/// Scale a sample of the given bit depth to the 0-65535 range.
+fn scale(sample: u16, depth: u8) -> u16 {
+    let max = (1u32 << depth) - 1;
+    (u32::from(sample) * 65535 / max) as u16
+}
+
+/// Iterates the samples of a scanline, most significant bits first.
+struct Samples<'a> {
+    row: &'a [u8],
+    depth: usize,
+    bit: usize,
+}
+
+impl<'a> Samples<'a> {
+    fn new(row: &'a [u8], depth: u8) -> Self {
+        Self {
+            row,
+            depth: usize::from(depth),
+            bit: 0,
+        }
+    }
+}
+
+impl Iterator for Samples<'_> {
+    type Item = u16;
+    fn next(&mut self) -> Option<u16> {
+        let byte = self.bit / 8;
+        let sample = match self.depth {
+            16 => u16::from_be_bytes([*self.row.get(byte)?, *self.row.get(byte + 1)?]),
+            8 => u16::from(*self.row.get(byte)?),
+            depth => {
+                let shift = 8 - depth - self.bit % 8;
+                u16::from(*self.row.get(byte)? >> shift) & ((1 << depth) - 1)
+            }
+        };
+
+        self.bit += self.depth;
+        Some(sample)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bare(ihdr: Ihdr) -> Header {
+        Header {
+            ihdr,
+            palette: None,
+            trns: None,
+            color_space: ColorSpace::default(),
+        }
+    }
+
+    fn header(bit_depth: u8, color_type: ColorType, width: u32) -> Header {
+        let ihdr = Ihdr {
+            bit_depth,
+            color_type,
+            ..Ihdr::new(width, 1)
+        };
+
+        Header {
+            palette: Some(Palette::new(PALETTE.to_vec()).unwrap()),
+            ..bare(ihdr)
+        }
+    }
+
+    fn decode(bit_depth: u8, color_type: ColorType, width: u32, rows: &[u8]) -> Vec<u32> {
+        let mut data = vec![0];
+        data.extend_from_slice(rows);
+        header(bit_depth, color_type, width)
+            .scanlines(&mut data, width, 1)
+            .unwrap()
+            .0
+    }
+
+    const PALETTE: [u32; 3] = [0xFF0000FF, 0x00FF00FF, 0x0000FF80];
+
+    #[test]
+    fn test_samples() {
+        let row = [0b1011_0001, 0b0111_1111];
+        assert_eq!(
+            Samples::new(&row, 1).take(8).collect::<Vec<u16>>(),
+            [1, 0, 1, 1, 0, 0, 0, 1]
+        );
+        assert_eq!(
+            Samples::new(&row, 2).take(4).collect::<Vec<u16>>(),
+            [2, 3, 0, 1]
+        );
+        assert_eq!(Samples::new(&row, 4).collect::<Vec<u16>>(), [11, 1, 7, 15]);
+        assert_eq!(Samples::new(&row, 16).collect::<Vec<u16>>(), [0xB17F]);
+    }
+
+    #[test]
+    fn test_scale() {
+        assert_eq!(scale(1, 1), 0xFFFF);
+        assert_eq!(scale(2, 2), 0xAAAA);
+        assert_eq!(scale(7, 4), 0x7777);
+        assert_eq!(scale(200, 8), 0xC8C8);
+        assert_eq!(scale(0x8080, 16), 0x8080);
+        assert_eq!(u32::from_rgba16([scale(0x8080, 16); 4]), 0x80808080);
+    }
+
+    #[test]
+    fn test_grayscale_low_depths() {
+        assert_eq!(
+            decode(1, ColorType::Grayscale, 3, &[0b1010_0000]),
+            [0xFFFFFFFF, 0x000000FF, 0xFFFFFFFF]
+        );
+        assert_eq!(
+            decode(2, ColorType::Grayscale, 2, &[0b0110_0000]),
+            [0x555555FF, 0xAAAAAAFF]
+        );
+        assert_eq!(
+            decode(4, ColorType::Grayscale, 3, &[0x0F, 0x80]),
+            [0x000000FF, 0xFFFFFFFF, 0x888888FF]
+        );
+    }
+
+    #[test]
+    fn test_grayscale_16() {
+        assert_eq!(
+            decode(16, ColorType::Grayscale, 2, &[0xFF, 0xFF, 0x12, 0x34]),
+            [0xFFFFFFFF, 0x121212FF]
+        );
+        assert_eq!(
+            decode(16, ColorType::GrayscaleAlpha, 1, &[0x40, 0x00, 0x80, 0x00]),
+            [0x40404080]
+        );
+    }
+
+    #[test]
+    fn test_deep_pixels() {
+        let mut data = [0, 0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0];
+        let (pixels, _) = header(16, ColorType::Rgba, 1)
+            .scanlines::<u64>(&mut data, 1, 1)
+            .unwrap();
+        assert_eq!(pixels, [0x123456789ABCDEF0]);
+
+        let mut data = [0, 0x12, 0x34];
+        let (pixels, _) = header(16, ColorType::Grayscale, 1)
+            .scanlines::<u64>(&mut data, 1, 1)
+            .unwrap();
+        assert_eq!(pixels, [0x123412341234FFFF]);
+
+        let mut data = [0, 0b1001_0000];
+        let (pixels, _) = header(2, ColorType::Indexed, 3)
+            .scanlines::<u64>(&mut data, 3, 1)
+            .unwrap();
+        assert_eq!(pixels[0], 0x00000000FFFF8080);
+    }
+
+    #[test]
+    fn test_grayscale_alpha() {
+        assert_eq!(
+            decode(8, ColorType::GrayscaleAlpha, 2, &[10, 20, 30, 40]),
+            [0x0A0A0A14, 0x1E1E1E28]
+        );
+    }
+
+    #[test]
+    fn test_rgb() {
+        assert_eq!(
+            decode(8, ColorType::Rgb, 2, &[1, 2, 3, 4, 5, 6]),
+            [0x010203FF, 0x040506FF]
+        );
+        assert_eq!(
+            decode(16, ColorType::Rgb, 1, &[0xAB, 0xAB, 0xCD, 0xCD, 0xEF, 0xEF]),
+            [0xABCDEFFF]
+        );
+        assert_eq!(
+            decode(16, ColorType::Rgba, 1, &[1, 0, 2, 0, 3, 0, 4, 0]),
+            [0x01020304]
+        );
+    }
+
+    #[test]
+    fn test_indexed() {
+        assert_eq!(
+            decode(2, ColorType::Indexed, 3, &[0b1001_0000]),
+            [0x0000FF80, 0x00FF00FF, 0xFF0000FF]
+        );
+        assert_eq!(
+            decode(8, ColorType::Indexed, 2, &[1, 0]),
+            [0x00FF00FF, 0xFF0000FF]
+        );
+    }
+
+    #[test]
+    fn test_indexed_out_of_range() {
+        let mut header = header(8, ColorType::Indexed, 1);
+        assert!(header.scanlines::<u32>(&mut [0, 3], 1, 1).is_err());
+
+        header.palette = None;
+        assert!(header.scanlines::<u32>(&mut [0, 0], 1, 1).is_err());
+    }
+
+    #[test]
+    fn test_trns_color_key() {
+        let mut gray = header(4, ColorType::Grayscale, 2);
+        gray.trns = Some(Trns::Gray(3));
+        assert_eq!(
+            gray.scanlines::<u32>(&mut [0, 0x3F], 2, 1).unwrap().0,
+            [0x33333300, 0xFFFFFFFF]
+        );
+
+        let mut rgb = header(16, ColorType::Rgb, 2);
+        rgb.trns = Some(Trns::Rgb(0x0101, 0x0202, 0x0303));
+        let mut data = [0, 1, 1, 2, 2, 3, 3, 1, 1, 2, 2, 3, 4];
+        assert_eq!(
+            rgb.scanlines::<u32>(&mut data, 2, 1).unwrap().0,
+            [0x01020300, 0x010203FF]
+        );
+    }
+
+    #[test]
+    fn test_short_data() {
+        let header = bare(Ihdr::new(2, 2));
+        assert!(header.scanlines::<u32>(&mut [0; 17], 2, 2).is_err());
+        assert!(header.scanlines::<u32>(&mut [0; 18], 2, 2).is_ok());
+    }
+}
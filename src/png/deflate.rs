@@ -0,0 +1,459 @@
+use crate::err::*;
+use miniz_oxide::{
+    deflate::core::{self, CompressorOxide, TDEFLFlush, TDEFLStatus},
+    mz_adler32_oxide, MZ_ADLER32_INIT,
+};
+use std::{io, io::Write, str::FromStr};
+
+/// How the compressor searches for matches, as in zlib's `deflateInit2`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum CompressionStrategy {
+    #[default]
+    Default,
+    /// Only use matches of at least five bytes, which suits filtered data.
+    Filtered,
+    /// Don't look for matches at all, only Huffman code the bytes.
+    HuffmanOnly,
+    /// Only look for runs of the previous byte.
+    Rle,
+}
+
+impl FromStr for CompressionStrategy {
+    type Err = PngErr;
+    fn from_str(strategy: &str) -> PngRes<Self> {
+        Ok(match strategy {
+            "default" => CompressionStrategy::Default,
+            "filtered" => CompressionStrategy::Filtered,
+            "huffman-only" => CompressionStrategy::HuffmanOnly,
+            "rle" => CompressionStrategy::Rle,
+            _ => return Err(PngErr::InvalidOption),
+        })
+    }
+}
+
+/// Settings for a zlib stream.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Deflate {
+    /// 0 (stored) to 9 (smallest).
+    pub level: u8,
+    pub strategy: CompressionStrategy,
+    /// Base 2 logarithm of the window size, from 8 to 15.
+    pub window: u8,
+}
+
+impl Default for Deflate {
+    fn default() -> Self {
+        Self {
+            level: 6,
+            strategy: CompressionStrategy::default(),
+            window: 15,
+        }
+    }
+}
+
+impl Deflate {
+    pub fn check(&self) -> PngRes {
+        PngErr::is_or(
+            self.level <= 9 && (8..=15).contains(&self.window),
+            PngErr::InvalidOption,
+        )
+    }
+
+    fn flags(&self) -> u32 {
+        let strategy = match self.strategy {
+            CompressionStrategy::Default => core::CompressionStrategy::Default,
+            CompressionStrategy::Filtered => core::CompressionStrategy::Filtered,
+            CompressionStrategy::HuffmanOnly => core::CompressionStrategy::HuffmanOnly,
+            CompressionStrategy::Rle => core::CompressionStrategy::RLE,
+        };
+
+        // A negative window asks for raw deflate, the zlib framing is ours.
+        core::create_comp_flags_from_zip_params(i32::from(self.level), -15, strategy as i32)
+    }
+
+    /// The two byte zlib header, recording the window size and level.
+    fn header(&self) -> [u8; 2] {
+        let cmf = (self.window - 8) << 4 | 8;
+        let level = match self.level {
+            0..=1 => 0,
+            2..=5 => 1,
+            6 => 2,
+            _ => 3,
+        };
+
+        let flg = level << 6;
+        let check = 31 - (u16::from(cmf) << 8 | u16::from(flg)) % 31;
+        [cmf, flg | (check % 31) as u8]
+    }
+}
+
+/// Raw deflate state, resetting the dictionary every `2^window` input
+/// bytes when the window is smaller than the 32 KiB the compressor always
+/// searches. No match can then reach further back than the header claims.
+struct Deflater {
+    compressor: Box<CompressorOxide>,
+    adler: u32,
+    window: usize,
+    pending: usize,
+}
+
+impl Deflater {
+    fn new(deflate: &Deflate) -> Self {
+        Self {
+            compressor: Box::new(CompressorOxide::new(deflate.flags())),
+            adler: MZ_ADLER32_INIT,
+            window: 1 << deflate.window,
+            pending: 0,
+        }
+    }
+
+    fn compress(&mut self, out: &mut impl Write, mut data: &[u8], flush: TDEFLFlush) -> PngRes {
+        loop {
+            let (status, used) =
+                core::compress_to_output(&mut self.compressor, data, flush, |buf| {
+                    out.write_all(buf).is_ok()
+                });
+
+            data = &data[used..];
+            match status {
+                TDEFLStatus::Okay | TDEFLStatus::Done if data.is_empty() => return Ok(()),
+                TDEFLStatus::Okay => (),
+                _ => return Err(PngErr::CompressError),
+            }
+        }
+    }
+
+    /// Compress as much of `data` as fits in the current window.
+    fn write(&mut self, out: &mut impl Write, data: &[u8]) -> PngRes<usize> {
+        let len = data.len().min(self.window - self.pending);
+        let data = &data[..len];
+
+        self.adler = mz_adler32_oxide(self.adler, data);
+        self.pending += len;
+
+        let flush = if self.pending == self.window {
+            self.pending = 0;
+            if self.window < 1 << 15 {
+                TDEFLFlush::Full
+            } else {
+                TDEFLFlush::None
+            }
+        } else {
+            TDEFLFlush::None
+        };
+
+        self.compress(out, data, flush)?;
+        Ok(len)
+    }
+
+    fn write_all(&mut self, out: &mut impl Write, mut data: &[u8]) -> PngRes {
+        while !data.is_empty() {
+            let len = self.write(out, data)?;
+            data = &data[len..];
+        }
+
+        Ok(())
+    }
+}
+
+/// Compresses everything written to it into a zlib stream on `inner`.
+pub struct ZlibWriter<W: Write> {
+    inner: W,
+    deflate: Deflate,
+    deflater: Deflater,
+    /// Whether anything has been written other than by `write_segments`.
+    written: bool,
+}
+
+impl<W: Write> ZlibWriter<W> {
+    pub fn new(mut inner: W, deflate: &Deflate) -> PngRes<Self> {
+        deflate.check()?;
+        inner
+            .write_all(&deflate.header())
+            .map_err(|_| PngErr::CompressError)?;
+
+        Ok(Self {
+            inner,
+            deflate: *deflate,
+            deflater: Deflater::new(deflate),
+            written: false,
+        })
+    }
+
+    /// Compress `count` segments on up to `threads` threads at a time, in
+    /// the manner of pigz. `segment` produces the data for each index, and
+    /// each is deflated on its own and ended on a byte boundary so the
+    /// pieces can be appended in order. Matches never cross a segment, so
+    /// the output is slightly larger than a serial stream.
+    ///
+    /// This must come before anything else is written to the stream, or it
+    /// fails with `CompressError`.
+    pub fn write_segments<F>(&mut self, count: usize, threads: usize, segment: F) -> PngRes
+    where
+        F: Fn(usize) -> PngRes<Vec<u8>> + Sync,
+    {
+        PngErr::not_or(self.written, PngErr::CompressError)?;
+        let (deflate, segment) = (&self.deflate, &segment);
+
+        for first in (0..count).step_by(threads.max(1)) {
+            let last = count.min(first + threads.max(1));
+            let parts = std::thread::scope(|scope| {
+                let handles: Vec<_> = (first..last)
+                    .map(|index| {
+                        scope.spawn(move || -> PngRes<(Vec<u8>, u32, usize)> {
+                            let data = segment(index)?;
+                            let mut deflater = Deflater::new(deflate);
+                            let mut out = Vec::with_capacity(data.len() / 2);
+
+                            deflater.write_all(&mut out, &data)?;
+                            deflater.compress(&mut out, &[], TDEFLFlush::Sync)?;
+                            Ok((out, deflater.adler, data.len()))
+                        })
+                    })
+                    .collect();
+
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().map_err(|_| PngErr::CompressError)?)
+                    .collect::<PngRes<Vec<_>>>()
+            })?;
+
+            for (out, adler, len) in parts {
+                self.inner
+                    .write_all(&out)
+                    .map_err(|_| PngErr::CompressError)?;
+                self.deflater.adler = adler32_combine(self.deflater.adler, adler, len);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finish the stream with the checksum and return the inner writer.
+    pub fn finish(mut self) -> PngRes<W> {
+        self.deflater
+            .compress(&mut self.inner, &[], TDEFLFlush::Finish)?;
+        self.inner
+            .write_all(&self.deflater.adler.to_be_bytes())
+            .map_err(|_| PngErr::CompressError)?;
+
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ZlibWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.written |= !data.is_empty();
+        self.deflater
+            .write(&mut self.inner, data)
+            .map_err(io::Error::other)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The Adler-32 of two pieces of data joined together, given the checksum
+/// of each and the length of the second.
+fn adler32_combine(first: u32, second: u32, len: usize) -> u32 {
+    const BASE: u32 = 65521;
+
+    let rem = (len % BASE as usize) as u32;
+    let low = (first & 0xFFFF) + (second & 0xFFFF) + BASE - 1;
+    let high = (rem * (first & 0xFFFF)) % BASE + (first >> 16) + (second >> 16) + BASE - rem;
+
+    ((high % BASE) << 16) | (low % BASE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs;
+
+    fn sample() -> Vec<u8> {
+        (0u32..100_000)
+            .map(|i| (i.wrapping_mul(0x9E3779B9) >> 29) as u8 + (i / 700) as u8)
+            .collect()
+    }
+
+    fn compress(data: &[u8], deflate: &Deflate) -> Vec<u8> {
+        let mut writer = ZlibWriter::new(Vec::new(), deflate).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_settings_round_trip() {
+        let data = sample();
+        let strategies = ["default", "filtered", "huffman-only", "rle"];
+
+        for strategy in strategies.map(|s| CompressionStrategy::from_str(s).unwrap()) {
+            for level in [0, 1, 6, 9] {
+                let deflate = Deflate {
+                    level,
+                    strategy,
+                    ..Deflate::default()
+                };
+
+                let zlib = compress(&data, &deflate);
+                assert_eq!(fs::decompress(&zlib).unwrap(), data);
+            }
+        }
+
+        let stored = compress(
+            &data,
+            &Deflate {
+                level: 0,
+                ..Deflate::default()
+            },
+        );
+        assert!(stored.len() > data.len());
+        assert!(compress(&data, &Deflate::default()).len() < data.len() / 2);
+    }
+
+    #[test]
+    fn test_header() {
+        for window in 8..=15 {
+            for level in 0..=9 {
+                let header = Deflate {
+                    level,
+                    window,
+                    ..Deflate::default()
+                }
+                .header();
+
+                assert_eq!(header[0], (window - 8) << 4 | 8);
+                assert_eq!((u16::from(header[0]) << 8 | u16::from(header[1])) % 31, 0);
+            }
+        }
+
+        assert_eq!(Deflate::default().header(), [0x78, 0x9C]);
+    }
+
+    #[test]
+    fn test_small_window_round_trip() {
+        let data = sample();
+        let small = compress(
+            &data,
+            &Deflate {
+                window: 9,
+                ..Deflate::default()
+            },
+        );
+        assert_eq!(small[0], 0x18);
+        assert_eq!(fs::decompress(&small).unwrap(), data);
+    }
+
+    #[test]
+    fn test_segments_form_one_stream() {
+        let data = sample();
+        let pieces: Vec<&[u8]> = data.chunks(7000).collect();
+
+        for (threads, window) in [(1, 15), (4, 15), (3, 10)] {
+            let deflate = Deflate {
+                window,
+                ..Deflate::default()
+            };
+            let mut writer = ZlibWriter::new(Vec::new(), &deflate).unwrap();
+            writer
+                .write_segments(pieces.len(), threads, |index| Ok(pieces[index].to_vec()))
+                .unwrap();
+
+            let zlib = writer.finish().unwrap();
+            assert_eq!(fs::decompress(&zlib).unwrap(), data);
+            assert_eq!(
+                zlib[zlib.len() - 4..],
+                mz_adler32_oxide(MZ_ADLER32_INIT, &data).to_be_bytes()
+            );
+        }
+
+        let mut writer = ZlibWriter::new(Vec::new(), &Deflate::default()).unwrap();
+        assert!(writer
+            .write_segments(3, 2, |index| match index {
+                1 => Err(PngErr::InvalidOption),
+                _ => Ok(vec![0; 10]),
+            })
+            .is_err());
+
+        let mut writer = ZlibWriter::new(Vec::new(), &Deflate::default()).unwrap();
+        writer.write_all(b"first").unwrap();
+        assert_eq!(
+            writer.write_segments(1, 1, |_| Ok(vec![0; 10])),
+            Err(PngErr::CompressError)
+        );
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture` to see how
+    /// much compressing on every core gains over one thread.
+    #[test]
+    #[ignore]
+    fn bench_segments() {
+        use std::time::Instant;
+
+        let data: Vec<u8> = (0..160)
+            .flat_map(|round| sample().into_iter().map(move |byte| byte ^ round as u8))
+            .collect();
+        let pieces: Vec<&[u8]> = data.chunks(1 << 20).collect();
+        let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+
+        let time = |threads: usize| {
+            let start = Instant::now();
+            let mut writer = ZlibWriter::new(Vec::new(), &Deflate::default()).unwrap();
+            writer
+                .write_segments(pieces.len(), threads, |index| Ok(pieces[index].to_vec()))
+                .unwrap();
+            writer.finish().unwrap();
+            start.elapsed()
+        };
+
+        let serial = time(1);
+        let parallel = time(cores);
+        println!(
+            "{} MiB: 1 thread {:?}, {} threads {:?}, {:.1}x faster",
+            data.len() >> 20,
+            serial,
+            cores,
+            parallel,
+            serial.as_secs_f64() / parallel.as_secs_f64()
+        );
+        if cores > 1 {
+            assert!(parallel < serial);
+        }
+    }
+
+    #[test]
+    fn test_adler32_combine() {
+        let data = sample();
+        let whole = mz_adler32_oxide(MZ_ADLER32_INIT, &data);
+
+        for split in [0, 1, 65521, 70000, data.len()] {
+            let (a, b) = data.split_at(split);
+            let first = mz_adler32_oxide(MZ_ADLER32_INIT, a);
+            let second = mz_adler32_oxide(MZ_ADLER32_INIT, b);
+            assert_eq!(adler32_combine(first, second, b.len()), whole);
+        }
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        for deflate in [
+            Deflate {
+                level: 10,
+                ..Deflate::default()
+            },
+            Deflate {
+                window: 7,
+                ..Deflate::default()
+            },
+            Deflate {
+                window: 16,
+                ..Deflate::default()
+            },
+        ] {
+            assert!(ZlibWriter::new(Vec::new(), &deflate).is_err());
+        }
+
+        assert!(CompressionStrategy::from_str("fast").is_err());
+    }
+}
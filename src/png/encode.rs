@@ -0,0 +1,61 @@
+use super::{Filter, FilterStrategy, PixelFormat};
+use crate::Color;
+
+/// Packs and filters one row at a time, remembering the previous row for
+/// the filters which refer to it.
+#[derive(Debug, Clone)]
+pub struct RowEncoder {
+    format: PixelFormat,
+    bpp: usize,
+    prev: Vec<u8>,
+    row: Vec<u8>,
+    out: Vec<u8>,
+}
+
+impl RowEncoder {
+    pub fn new(format: &PixelFormat, width: u32) -> Self {
+        let ihdr = format.ihdr(width, 1);
+        let stride = ihdr.stride(width).unwrap_or(0);
+
+        Self {
+            format: format.clone(),
+            bpp: ihdr.bpp(),
+            prev: vec![0; stride],
+            row: Vec::with_capacity(stride),
+            out: vec![0; stride + 1],
+        }
+    }
+
+    pub fn format(&self) -> &PixelFormat {
+        &self.format
+    }
+
+    /// Treat `pixels` as the previous row, so that encoding can begin part
+    /// way down an image.
+    pub fn seed<P: Color>(&mut self, pixels: &[P]) {
+        let stride = self.prev.len();
+        self.prev.clear();
+        self.format.pack(pixels, &mut self.prev);
+        self.prev.resize(stride, 0);
+    }
+
+    /// Encode the next row, returning its filter type byte followed by the
+    /// filtered data. `recorded` is the filter used by `Preserve`.
+    pub fn encode<P: Color>(
+        &mut self,
+        pixels: &[P],
+        recorded: u8,
+        strategy: FilterStrategy,
+    ) -> &[u8] {
+        self.row.clear();
+        self.format.pack(pixels, &mut self.row);
+        self.row.resize(self.prev.len(), 0);
+
+        let recorded = Filter::try_from(recorded).unwrap_or(Filter::None);
+        let (ft, out) = self.out.split_at_mut(1);
+        ft[0] = strategy.select(recorded, self.bpp, &self.prev, &self.row, out) as u8;
+
+        std::mem::swap(&mut self.prev, &mut self.row);
+        &self.out
+    }
+}
@@ -0,0 +1,298 @@
+use crate::{err::*, fs};
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Filter {
+    None = 0,
+    Sub = 1,
+    Up = 2,
+    Average = 3,
+    Paeth = 4,
+}
+
+impl Filter {
+    pub const ALL: [Filter; 5] = [
+        Filter::None,
+        Filter::Sub,
+        Filter::Up,
+        Filter::Average,
+        Filter::Paeth,
+    ];
+
+    /// Reverse this filter in place. `prev` is the reconstructed previous
+    /// scanline (all zeroes for the first row) and `bpp` is the number of
+    /// bytes per complete pixel, rounded up to 1.
+    pub fn unfilter(self, bpp: usize, prev: &[u8], row: &mut [u8]) {
+        match self {
+            Filter::None => (),
+            Filter::Sub => {
+                for i in bpp..row.len() {
+                    row[i] = row[i].wrapping_add(row[i - bpp]);
+                }
+            }
+            Filter::Up => {
+                for (byte, up) in row.iter_mut().zip(prev) {
+                    *byte = byte.wrapping_add(*up);
+                }
+            }
+            Filter::Average => {
+                for i in 0..row.len() {
+                    let left = if i >= bpp { row[i - bpp] } else { 0 };
+                    let avg = (u16::from(left) + u16::from(prev[i])) / 2;
+                    row[i] = row[i].wrapping_add(avg as u8);
+                }
+            }
+            Filter::Paeth => {
+                for i in 0..row.len() {
+                    let (left, upleft) = if i >= bpp {
+                        (row[i - bpp], prev[i - bpp])
+                    } else {
+                        (0, 0)
+                    };
+                    row[i] = row[i].wrapping_add(paeth(left, prev[i], upleft));
+                }
+            }
+        }
+    }
+
+    /// Apply this filter to `row`, writing the filtered bytes to `out`.
+    pub fn filter(self, bpp: usize, prev: &[u8], row: &[u8], out: &mut [u8]) {
+        for i in 0..row.len() {
+            let (left, upleft) = if i >= bpp {
+                (row[i - bpp], prev[i - bpp])
+            } else {
+                (0, 0)
+            };
+
+            let predict = match self {
+                Filter::None => 0,
+                Filter::Sub => left,
+                Filter::Up => prev[i],
+                Filter::Average => ((u16::from(left) + u16::from(prev[i])) / 2) as u8,
+                Filter::Paeth => paeth(left, prev[i], upleft),
+            };
+
+            out[i] = row[i].wrapping_sub(predict);
+        }
+    }
+}
+
+/// How the encoder chooses a filter for each scanline.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum FilterStrategy {
+    /// Use the filter recorded against each row of the `Img`.
+    Preserve,
+    /// Use the same filter for every row.
+    Fixed(Filter),
+    /// Pick the filter with the minimum sum of absolute differences.
+    #[default]
+    MinSum,
+    /// Compress every candidate and keep whichever is smallest.
+    BruteForce,
+}
+
+impl FilterStrategy {
+    /// Filter `row` into `out`, returning the filter that was chosen.
+    pub fn select(
+        self,
+        recorded: Filter,
+        bpp: usize,
+        prev: &[u8],
+        row: &[u8],
+        out: &mut [u8],
+    ) -> Filter {
+        match self {
+            FilterStrategy::Preserve => {
+                recorded.filter(bpp, prev, row, out);
+                recorded
+            }
+            FilterStrategy::Fixed(filter) => {
+                filter.filter(bpp, prev, row, out);
+                filter
+            }
+            FilterStrategy::MinSum => best_by(bpp, prev, row, out, |data| {
+                data.iter()
+                    .map(|&byte| u64::from((byte as i8).unsigned_abs()))
+                    .sum::<u64>()
+            }),
+            FilterStrategy::BruteForce => best_by(bpp, prev, row, out, |data| {
+                fs::compress(data).map_or(usize::MAX, |data| data.len())
+            }),
+        }
+    }
+}
+
+impl FromStr for FilterStrategy {
+    type Err = PngErr;
+    fn from_str(strategy: &str) -> PngRes<Self> {
+        Ok(match strategy {
+            "preserve" => FilterStrategy::Preserve,
+            "none" => FilterStrategy::Fixed(Filter::None),
+            "sub" => FilterStrategy::Fixed(Filter::Sub),
+            "up" => FilterStrategy::Fixed(Filter::Up),
+            "average" => FilterStrategy::Fixed(Filter::Average),
+            "paeth" => FilterStrategy::Fixed(Filter::Paeth),
+            "min-sum" => FilterStrategy::MinSum,
+            "brute-force" => FilterStrategy::BruteForce,
+            _ => return Err(PngErr::InvalidOption),
+        })
+    }
+}
+
+fn best_by<K: Ord>(
+    bpp: usize,
+    prev: &[u8],
+    row: &[u8],
+    out: &mut [u8],
+    cost: impl Fn(&[u8]) -> K,
+) -> Filter {
+    let mut candidate = vec![0u8; row.len()];
+    let mut best: Option<(K, Filter)> = None;
+
+    for filter in Filter::ALL {
+        filter.filter(bpp, prev, row, &mut candidate);
+        let score = cost(&candidate);
+
+        if best.as_ref().is_none_or(|(lowest, _)| score < *lowest) {
+            out.copy_from_slice(&candidate);
+            best = Some((score, filter));
+        }
+    }
+
+    best.map_or(Filter::None, |(_, filter)| filter)
+}
+
+impl TryFrom<u8> for Filter {
+    type Error = PngErr;
+    fn try_from(byte: u8) -> PngRes<Self> {
+        Filter::ALL
+            .get(usize::from(byte))
+            .copied()
+            .ok_or(PngErr::InvalidFilter)
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let (ia, ib, ic) = (i16::from(a), i16::from(b), i16::from(c));
+    let p = ia + ib - ic;
+    let (pa, pb, pc) = ((p - ia).abs(), (p - ib).abs(), (p - ic).abs());
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two 2px RGB scanlines, as raw bytes and as filtered by each filter
+    // type (worked by hand from the PNG specification).
+    const PREV: [u8; 6] = [10, 20, 30, 200, 50, 25];
+    const ROW: [u8; 6] = [15, 25, 35, 50, 45, 70];
+
+    const SUB: [u8; 6] = [15, 25, 35, 35, 20, 35];
+    const UP: [u8; 6] = [5, 5, 5, 106, 251, 45];
+    const AVERAGE: [u8; 6] = [10, 15, 20, 199, 8, 40];
+    const PAETH: [u8; 6] = [5, 5, 5, 106, 251, 40];
+
+    fn unfiltered(filter: Filter, data: &[u8]) -> Vec<u8> {
+        let mut row = data.to_vec();
+        filter.unfilter(3, &PREV, &mut row);
+        row
+    }
+
+    fn filtered(filter: Filter) -> Vec<u8> {
+        let mut out = vec![0; ROW.len()];
+        filter.filter(3, &PREV, &ROW, &mut out);
+        out
+    }
+
+    #[test]
+    fn test_filter_from_byte() {
+        assert_eq!(Filter::try_from(0).unwrap(), Filter::None);
+        assert_eq!(Filter::try_from(4).unwrap(), Filter::Paeth);
+        assert!(Filter::try_from(5).is_err());
+    }
+
+    #[test]
+    fn test_unfilter_fixtures() {
+        assert_eq!(unfiltered(Filter::None, &ROW), ROW);
+        assert_eq!(unfiltered(Filter::Sub, &SUB), ROW);
+        assert_eq!(unfiltered(Filter::Up, &UP), ROW);
+        assert_eq!(unfiltered(Filter::Average, &AVERAGE), ROW);
+        assert_eq!(unfiltered(Filter::Paeth, &PAETH), ROW);
+    }
+
+    #[test]
+    fn test_filter_fixtures() {
+        assert_eq!(filtered(Filter::None), ROW);
+        assert_eq!(filtered(Filter::Sub), SUB);
+        assert_eq!(filtered(Filter::Up), UP);
+        assert_eq!(filtered(Filter::Average), AVERAGE);
+        assert_eq!(filtered(Filter::Paeth), PAETH);
+    }
+
+    #[test]
+    fn test_first_row() {
+        let zero = [0u8; 6];
+        for filter in Filter::ALL {
+            let mut out = vec![0; ROW.len()];
+            filter.filter(3, &zero, &ROW, &mut out);
+            filter.unfilter(3, &zero, &mut out);
+            assert_eq!(out, ROW);
+        }
+    }
+
+    #[test]
+    fn test_fixed_strategy() {
+        let mut out = vec![0; ROW.len()];
+        let strategy = FilterStrategy::Fixed(Filter::Average);
+        let chosen = strategy.select(Filter::Up, 3, &PREV, &ROW, &mut out);
+        assert_eq!(chosen, Filter::Average);
+        assert_eq!(out, AVERAGE);
+
+        let chosen = FilterStrategy::Preserve.select(Filter::Up, 3, &PREV, &ROW, &mut out);
+        assert_eq!(chosen, Filter::Up);
+        assert_eq!(out, UP);
+    }
+
+    #[test]
+    fn test_adaptive_strategies() {
+        let ramp: Vec<u8> = (0..64).map(|x| x * 3).collect();
+        let zero = vec![0u8; ramp.len()];
+        let mut out = vec![0; ramp.len()];
+
+        for strategy in [FilterStrategy::MinSum, FilterStrategy::BruteForce] {
+            let chosen = strategy.select(Filter::None, 1, &zero, &ramp, &mut out);
+            assert_eq!(chosen, Filter::Sub);
+            chosen.unfilter(1, &zero, &mut out);
+            assert_eq!(out, ramp);
+        }
+    }
+
+    #[test]
+    fn test_strategy_from_str() {
+        assert_eq!(
+            FilterStrategy::from_str("paeth").unwrap(),
+            FilterStrategy::Fixed(Filter::Paeth)
+        );
+        assert_eq!(
+            FilterStrategy::from_str("min-sum").unwrap(),
+            FilterStrategy::MinSum
+        );
+        assert!(FilterStrategy::from_str("fastest").is_err());
+    }
+
+    #[test]
+    fn test_paeth_predictor() {
+        assert_eq!(paeth(10, 20, 30), 10);
+        assert_eq!(paeth(40, 50, 20), 50);
+        assert_eq!(paeth(50, 40, 20), 50);
+        assert_eq!(paeth(20, 40, 50), 20);
+    }
+}
@@ -0,0 +1,286 @@
+use super::{Chunk, ColorType, Ihdr, Palette, Trns};
+use crate::{calc, convert, err::*, Color};
+use std::collections::{HashMap, HashSet};
+
+/// The color type, bit depth and palette used to encode RGBA pixels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PixelFormat {
+    pub color_type: ColorType,
+    pub bit_depth: u8,
+    palette: Option<Palette>,
+    lookup: HashMap<u32, u8>,
+}
+
+impl PixelFormat {
+    pub fn new(color_type: ColorType, bit_depth: u8) -> Self {
+        Self {
+            color_type,
+            bit_depth,
+            palette: None,
+            lookup: HashMap::new(),
+        }
+    }
+
+    /// RGBA at the channel depth of `P`.
+    pub fn rgba<P: Color>() -> Self {
+        Self::new(ColorType::Rgba, P::DEPTH)
+    }
+
+    pub fn indexed(palette: Palette) -> Self {
+        let bit_depth = match palette.colors().len() {
+            0..=2 => 1,
+            3..=4 => 2,
+            5..=16 => 4,
+            _ => 8,
+        };
+
+        Self {
+            lookup: (0u8..=255)
+                .zip(palette.colors())
+                .map(|(i, px)| (*px, i))
+                .collect(),
+            palette: Some(palette),
+            ..Self::new(ColorType::Indexed, bit_depth)
+        }
+    }
+
+    /// Find the smallest format which can store every pixel in `data`
+    /// without loss. 16-bit pixels are only written at 16 bits when some
+    /// channel cannot be narrowed exactly.
+    pub fn analyse<P: Color>(data: &[P], width: u32) -> Self {
+        let opaque = data.iter().all(|px| px.to_rgba16()[3] == u16::MAX);
+        let gray = data.iter().all(|px| {
+            let [r, g, b, _] = px.to_rgba16();
+            r == g && g == b
+        });
+        let depth = if data.iter().all(|px| px.fits_rgba8()) {
+            8
+        } else {
+            16
+        };
+
+        let mut candidates = vec![match (gray, opaque) {
+            (true, true) => Self::new(ColorType::Grayscale, gray_depth(data)),
+            (true, false) => Self::new(ColorType::GrayscaleAlpha, depth),
+            (false, true) => Self::new(ColorType::Rgb, depth),
+            (false, false) => Self::new(ColorType::Rgba, depth),
+        }];
+
+        if depth == 8 {
+            if let Some(palette) = distinct(data, Palette::MAX_ENTRIES) {
+                candidates.push(Self::indexed(palette));
+            }
+        }
+
+        let rows = data.len() / convert!(ex usize; width).max(1);
+        candidates
+            .into_iter()
+            .min_by_key(|format| format.encoded_size(width, rows))
+            .unwrap_or_else(Self::rgba::<P>)
+    }
+
+    /// Approximate bytes needed for the scanlines plus any palette.
+    fn encoded_size(&self, width: u32, rows: usize) -> usize {
+        let stride = self.ihdr(width, 1).stride(width).unwrap_or(usize::MAX);
+        let palette = self.palette.as_ref().map_or(0, |palette| {
+            palette.colors().len() * 3 + palette.alpha().len()
+        });
+
+        calc!(stride + 1 * rows + palette)
+    }
+
+    pub fn ihdr(&self, width: u32, height: u32) -> Ihdr {
+        Ihdr {
+            bit_depth: self.bit_depth,
+            color_type: self.color_type,
+            ..Ihdr::new(width, height)
+        }
+    }
+
+    /// Chunks which must be written between IHDR and IDAT.
+    pub fn chunks(&self) -> PngRes<Vec<Chunk>> {
+        let mut chunks = Vec::new();
+
+        if let Some(palette) = &self.palette {
+            chunks.push(Chunk::plte(palette)?);
+
+            let alpha = palette.alpha();
+            if !alpha.is_empty() {
+                chunks.push(Chunk::trns(&Trns::Palette(alpha))?);
+            }
+        }
+
+        Ok(chunks)
+    }
+
+    /// Append a row of pixels to `out` as unfiltered scanline bytes.
+    pub fn pack<P: Color>(&self, row: &[P], out: &mut Vec<u8>) {
+        let depth = self.bit_depth;
+        let channels: &[usize] = match self.color_type {
+            ColorType::Rgba => &[0, 1, 2, 3],
+            ColorType::Rgb => &[0, 1, 2],
+            ColorType::GrayscaleAlpha => &[0, 3],
+            ColorType::Grayscale => &[0],
+            ColorType::Indexed => {
+                return pack_bits(
+                    row.iter()
+                        .map(|px| self.lookup.get(&px.to_rgba8()).copied().unwrap_or(0)),
+                    depth,
+                    out,
+                );
+            }
+        };
+
+        let samples = row.iter().flat_map(|px| {
+            let rgba = px.to_rgba16();
+            channels.iter().map(move |&ch| rgba[ch])
+        });
+
+        if depth == 16 {
+            out.extend(samples.flat_map(u16::to_be_bytes));
+        } else {
+            pack_bits(samples.map(|v| (v >> (16 - depth)) as u8), depth, out);
+        }
+    }
+}
+
+/// The lowest bit depth able to hold every gray level exactly.
+fn gray_depth<P: Color>(data: &[P]) -> u8 {
+    let levels: HashSet<u16> = data.iter().map(|px| px.to_rgba16()[0]).collect();
+
+    [1u8, 2, 4, 8]
+        .into_iter()
+        .find(|&depth| {
+            let step = u16::MAX / ((1u16 << depth) - 1);
+            levels.iter().all(|level| level % step == 0)
+        })
+        .unwrap_or(16)
+}
+
+/// The distinct colors in `data` as a palette, or `None` if there are more
+/// than `max`. Translucent entries are placed first to shorten tRNS.
+fn distinct<P: Color>(data: &[P], max: usize) -> Option<Palette> {
+    let mut colors = HashSet::new();
+    for px in data {
+        if colors.insert(px.to_rgba8()) && colors.len() > max {
+            return None;
+        }
+    }
+
+    let mut colors: Vec<u32> = colors.into_iter().collect();
+    colors.sort_unstable_by_key(|px| (px & 0xFF == 0xFF, *px));
+    Palette::new(colors).ok()
+}
+
+/// Pack samples of `depth` bits each, most significant bits first.
+fn pack_bits(samples: impl Iterator<Item = u8>, depth: u8, out: &mut Vec<u8>) {
+    if depth == 8 {
+        out.extend(samples);
+        return;
+    }
+
+    let (mut byte, mut used) = (0u8, 0u8);
+    for sample in samples {
+        byte |= sample << (8 - depth - used);
+        used += depth;
+
+        if used == 8 {
+            out.push(byte);
+            byte = 0;
+            used = 0;
+        }
+    }
+
+    if used > 0 {
+        out.push(byte);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{col, Color};
+
+    fn packed(format: &PixelFormat, row: &[u32]) -> Vec<u8> {
+        let mut out = Vec::new();
+        format.pack(row, &mut out);
+        out
+    }
+
+    #[test]
+    fn test_analyse_grayscale() {
+        let bw = [col!(0, 0, 0), col!(255, 255, 255)];
+        assert_eq!(
+            PixelFormat::analyse(&bw, 2),
+            PixelFormat::new(ColorType::Grayscale, 1)
+        );
+
+        let quarters = [col!(0, 0, 0), col!(85, 85, 85), col!(170, 170, 170)];
+        let format = PixelFormat::analyse(&quarters.repeat(8), 24);
+        assert_eq!(
+            (format.color_type, format.bit_depth),
+            (ColorType::Grayscale, 2)
+        );
+
+        let ramp: Vec<u32> = (0..=255).map(|v| col!(v, v, v)).collect();
+        let format = PixelFormat::analyse(&ramp, 16);
+        assert_eq!(
+            (format.color_type, format.bit_depth),
+            (ColorType::Grayscale, 8)
+        );
+
+        let alpha = [col!(10, 10, 10, 0), col!(20, 20, 20, 128)];
+        let format = PixelFormat::analyse(&alpha, 2);
+        assert_eq!(format.color_type, ColorType::GrayscaleAlpha);
+    }
+
+    #[test]
+    fn test_analyse_color() {
+        let few = [col!(255, 0, 0), col!(0, 255, 0), col!(0, 0, 255)].repeat(100);
+        let format = PixelFormat::analyse(&few, 30);
+        assert_eq!(
+            (format.color_type, format.bit_depth),
+            (ColorType::Indexed, 2)
+        );
+        assert_eq!(format.palette.as_ref().unwrap().colors().len(), 3);
+        assert_eq!(format.chunks().unwrap().len(), 1);
+
+        let alpha = [col!(255, 0, 0), col!(0, 255, 0, 0), col!(0, 0, 255, 128)].repeat(100);
+        let format = PixelFormat::analyse(&alpha, 30);
+        assert_eq!(format.color_type, ColorType::Indexed);
+        assert_eq!(format.palette.as_ref().unwrap().alpha(), [128, 0]);
+        assert_eq!(format.chunks().unwrap()[1].data(), [128, 0]);
+
+        let many: Vec<u32> = (0..1024).map(|v: u32| (v << 8) | 0xFF).collect();
+        let format = PixelFormat::analyse(&many, 32);
+        assert_eq!(format.color_type, ColorType::Rgb);
+
+        let translucent: Vec<u32> = (0..1024).map(|v: u32| (v << 12) | 0x80).collect();
+        let format = PixelFormat::analyse(&translucent, 32);
+        assert_eq!(format.color_type, ColorType::Rgba);
+    }
+
+    #[test]
+    fn test_pack() {
+        let gray = PixelFormat::new(ColorType::Grayscale, 1);
+        let row = [col!(0xFFFFFF), col!(0), col!(0xFFFFFF)];
+        assert_eq!(packed(&gray, &row), [0b1010_0000]);
+
+        let gray = PixelFormat::new(ColorType::Grayscale, 4);
+        assert_eq!(packed(&gray, &row), [0xF0, 0xF0]);
+
+        let rgb = PixelFormat::new(ColorType::Rgb, 8);
+        assert_eq!(packed(&rgb, &[col!(1, 2, 3)]), [1, 2, 3]);
+
+        let gray_alpha = PixelFormat::new(ColorType::GrayscaleAlpha, 8);
+        assert_eq!(packed(&gray_alpha, &[col!(9, 9, 9, 4)]), [9, 4]);
+
+        let palette = Palette::new(vec![col!(0), col!(0xFF0000), col!(0xFF)]).unwrap();
+        let indexed = PixelFormat::indexed(palette);
+        assert_eq!(packed(&indexed, &[col!(0xFF), col!(0)]), [0b1000_0000]);
+        assert_eq!(
+            indexed.chunks().unwrap()[0].data(),
+            [0, 0, 0, 255, 0, 0, 0, 0, 255]
+        );
+    }
+}
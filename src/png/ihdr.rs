@@ -0,0 +1,231 @@
+use super::{chunk::segment4, Chunk, ChunkType};
+use crate::{convert, err::*, INT_MAX};
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale = 0,
+    Rgb = 2,
+    Indexed = 3,
+    GrayscaleAlpha = 4,
+    Rgba = 6,
+}
+
+impl ColorType {
+    pub fn channels(self) -> usize {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    pub fn bit_depths(self) -> &'static [u8] {
+        match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            _ => &[8, 16],
+        }
+    }
+}
+
+impl TryFrom<u8> for ColorType {
+    type Error = PngErr;
+    fn try_from(byte: u8) -> PngRes<Self> {
+        Ok(match byte {
+            0 => ColorType::Grayscale,
+            2 => ColorType::Rgb,
+            3 => ColorType::Indexed,
+            4 => ColorType::GrayscaleAlpha,
+            6 => ColorType::Rgba,
+            _ => return Err(PngErr::InvalidColorType),
+        })
+    }
+}
+
+impl fmt::Display for ColorType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ColorType::Grayscale => "Grayscale",
+            ColorType::Rgb => "RGB",
+            ColorType::Indexed => "Indexed",
+            ColorType::GrayscaleAlpha => "Grayscale + Alpha",
+            ColorType::Rgba => "RGBA",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// The decoded contents of an IHDR chunk.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Ihdr {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub compression: u8,
+    pub filter: u8,
+    pub interlace: u8,
+}
+
+impl Ihdr {
+    /// A non-interlaced, 8-bit RGBA header.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            bit_depth: 8,
+            color_type: ColorType::Rgba,
+            compression: 0,
+            filter: 0,
+            interlace: 0,
+        }
+    }
+
+    pub fn validate(&self) -> PngRes {
+        PngErr::not_or(self.width > INT_MAX, PngErr::IHDRWidthOverflow)?;
+        PngErr::not_or(self.height > INT_MAX, PngErr::IHDRHeightOverflow)?;
+        PngErr::not_or(self.width == 0 || self.height == 0, PngErr::InvalidIHDR)?;
+        PngErr::is_or(
+            self.color_type.bit_depths().contains(&self.bit_depth),
+            PngErr::InvalidBitDepth,
+        )?;
+        PngErr::is_or(
+            self.compression == 0 && self.filter == 0 && self.interlace <= 1,
+            PngErr::InvalidIHDR,
+        )
+    }
+
+    pub fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() * usize::from(self.bit_depth)
+    }
+
+    /// Bytes per complete pixel as used by the scanline filters, which is
+    /// never less than 1.
+    pub fn bpp(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8).max(1)
+    }
+
+    /// Length of a scanline `width` pixels wide, excluding the filter byte.
+    pub fn stride(&self, width: u32) -> PngRes<usize> {
+        let bits = convert!(usize; width)?
+            .checked_mul(self.bits_per_pixel())
+            .ok_or(PngErr::IntOverflow)?;
+
+        Ok(bits.div_ceil(8))
+    }
+
+    pub fn to_chunk(self) -> PngRes<Chunk> {
+        self.validate()?;
+
+        Ok(Chunk::new(
+            ChunkType::from_str("IHDR")?,
+            self.width
+                .to_be_bytes()
+                .iter()
+                .chain(self.height.to_be_bytes().iter())
+                .chain(
+                    [
+                        self.bit_depth,
+                        self.color_type as u8,
+                        self.compression,
+                        self.filter,
+                        self.interlace,
+                    ]
+                    .iter(),
+                )
+                .copied()
+                .collect(),
+        ))
+    }
+}
+
+impl TryFrom<&Chunk> for Ihdr {
+    type Error = PngErr;
+    fn try_from(chunk: &Chunk) -> PngRes<Self> {
+        let data = chunk.data();
+        PngErr::is_or(data.len() == 13, PngErr::InvalidIHDR)?;
+
+        let ihdr = Self {
+            width: u32::from_be_bytes(segment4(&data[0..4])?),
+            height: u32::from_be_bytes(segment4(&data[4..8])?),
+            bit_depth: data[8],
+            color_type: ColorType::try_from(data[9])?,
+            compression: data[10],
+            filter: data[11],
+            interlace: data[12],
+        };
+
+        ihdr.validate()?;
+        Ok(ihdr)
+    }
+}
+
+impl fmt::Display for Ihdr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}x{}, {}-bit {}",
+            self.width, self.height, self.bit_depth, self.color_type
+        )?;
+
+        if self.interlace == 1 {
+            write!(f, ", interlaced")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(bit_depth: u8, color_type: ColorType) -> Ihdr {
+        Ihdr {
+            bit_depth,
+            color_type,
+            ..Ihdr::new(10, 5)
+        }
+    }
+
+    #[test]
+    fn test_ihdr_round_trip() {
+        let ihdr = header(4, ColorType::Indexed);
+        let chunk = ihdr.to_chunk().unwrap();
+        assert_eq!(chunk.length(), 13);
+        assert_eq!(Ihdr::try_from(&chunk).unwrap(), ihdr);
+    }
+
+    #[test]
+    fn test_ihdr_invalid_combinations() {
+        assert!(header(16, ColorType::Indexed).validate().is_err());
+        assert!(header(4, ColorType::Rgb).validate().is_err());
+        assert!(header(2, ColorType::GrayscaleAlpha).validate().is_err());
+        assert!(header(3, ColorType::Grayscale).validate().is_err());
+        assert!(Ihdr::new(0, 10).validate().is_err());
+
+        let interlace = Ihdr {
+            interlace: 2,
+            ..Ihdr::new(1, 1)
+        };
+        assert!(interlace.validate().is_err());
+    }
+
+    #[test]
+    fn test_ihdr_invalid_color_type() {
+        assert!(ColorType::try_from(1).is_err());
+        assert!(ColorType::try_from(7).is_err());
+    }
+
+    #[test]
+    fn test_ihdr_stride() {
+        assert_eq!(header(1, ColorType::Grayscale).stride(10).unwrap(), 2);
+        assert_eq!(header(4, ColorType::Indexed).stride(3).unwrap(), 2);
+        assert_eq!(header(16, ColorType::Rgb).stride(10).unwrap(), 60);
+        assert_eq!(header(1, ColorType::Grayscale).bpp(), 1);
+        assert_eq!(header(16, ColorType::Rgba).bpp(), 8);
+    }
+}
@@ -0,0 +1,74 @@
+use crate::err::*;
+
+/// The first byte of a message chunk says what the rest of it holds, so a
+/// message is never taken for another kind whatever bytes it starts with.
+/// None of them can begin UTF-8 text, so a chunk written before messages
+/// were marked still reads as the plain text it holds.
+const PLAIN: u8 = 0xF8;
+const LOCKED: u8 = 0xF9;
+/// Marks one part of a split message. The message the parts make up is
+/// marked too, once they are put back together.
+pub(super) const PART: u8 = 0xFA;
+
+/// A message hidden in a chunk, or put back together from parts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Plain(Vec<u8>),
+    /// Encrypted by `crypt`, so it needs a passphrase or key to read.
+    Locked(Vec<u8>),
+}
+
+impl Message {
+    /// The message as it is stored: its marker, then its data.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (marker, data) = match self {
+            Message::Plain(data) => (PLAIN, data),
+            Message::Locked(data) => (LOCKED, data),
+        };
+        [&[marker][..], data].concat()
+    }
+
+    /// Read a stored message. Data with no marker is plain, as it was
+    /// written before messages were marked, while a part on its own is
+    /// `InvalidPart`.
+    pub(super) fn parse(data: &[u8]) -> PngRes<Self> {
+        Ok(match data.split_first() {
+            Some((&PLAIN, data)) => Message::Plain(data.to_vec()),
+            Some((&LOCKED, data)) => Message::Locked(data.to_vec()),
+            Some((&PART, _)) => return Err(PngErr::InvalidPart),
+            _ => Message::Plain(data.to_vec()),
+        })
+    }
+}
+
+impl From<&str> for Message {
+    fn from(text: &str) -> Self {
+        Message::Plain(text.as_bytes().to_vec())
+    }
+}
+
+impl From<&[u8]> for Message {
+    fn from(data: &[u8]) -> Self {
+        Message::Plain(data.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markers() {
+        for data in [&b""[..], b"text", &[PLAIN, 1], &[LOCKED, 2], &[PART, 3]] {
+            let plain = Message::from(data);
+            assert_eq!(Message::parse(&plain.to_bytes()), Ok(plain));
+
+            let locked = Message::Locked(data.to_vec());
+            assert_eq!(Message::parse(&locked.to_bytes()), Ok(locked));
+        }
+
+        assert_eq!(Message::parse(b"unmarked"), Ok(Message::from("unmarked")));
+        assert_eq!(Message::parse(b""), Ok(Message::from("")));
+        assert_eq!(Message::parse(&[PART, 3]), Err(PngErr::InvalidPart));
+    }
+}
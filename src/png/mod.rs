@@ -1,5 +1,45 @@
+pub mod adam7;
+mod apng;
 mod chunk;
 mod chunk_type;
+mod color_space;
+pub mod crypt;
+mod decode;
+mod deflate;
+mod encode;
+mod filter;
+mod format;
+mod ihdr;
+mod message;
+mod optimize;
+mod options;
+mod palette;
+mod placement;
 mod png;
+mod reader;
+mod recover;
+mod sign;
+mod split;
+mod validate;
+mod writer;
 
-pub use {chunk::*, chunk_type::ChunkType, png::Png};
+pub use {
+    apng::{Animation, Frame},
+    chunk::*,
+    chunk_type::ChunkType,
+    color_space::{ColorSpace, RenderingIntent},
+    decode::Header,
+    deflate::{CompressionStrategy, Deflate, ZlibWriter},
+    filter::{Filter, FilterStrategy},
+    format::PixelFormat,
+    ihdr::{ColorType, Ihdr},
+    message::Message,
+    options::{DecodeOptions, EncodeOptions},
+    palette::{Palette, Trns},
+    placement::Placement,
+    png::Png,
+    reader::ChunkReader,
+    sign::{Signer, Verification, Verifier},
+    validate::{validate, Problem, Violation},
+    writer::{ChunkSink, ChunkWriter, ImageWriter},
+};
@@ -0,0 +1,213 @@
+use super::{Animation, Chunk, CompressionStrategy, EncodeOptions, Filter, FilterStrategy, Png};
+use crate::{err::*, img::Img};
+
+/// Chunks which are written by the encoder itself rather than copied.
+const REGENERATED: [&str; 12] = [
+    "IHDR", "PLTE", "IDAT", "IEND", "tRNS", "gAMA", "cHRM", "sRGB", "iCCP", "acTL", "fcTL", "fdAT",
+];
+
+/// Chunks whose contents depend on the color type, bit depth or palette,
+/// so are only valid while those stay the same.
+const FORMAT_DEPENDENT: [&str; 3] = ["bKGD", "sBIT", "hIST"];
+
+/// Largest IDAT written, which in practice means a single chunk.
+const IDAT_SIZE: usize = 1 << 30;
+
+impl Png {
+    /// Re-encode the image losslessly with every combination of scanline
+    /// filter and compression strategy, at the smallest color type, and
+    /// return whichever is smallest. Image data is merged into one IDAT.
+    ///
+    /// Other chunks are kept in place unless `strip` is set, though the
+    /// color space chunks are always kept since they change how the pixels
+    /// look. bKGD, sBIT and hIST are dropped if the color type changes.
+    pub fn optimize(&self, strip: bool) -> PngRes<Png> {
+        let trials = Self::trials();
+
+        let encoded = if self.chunk_by_type("acTL").is_some() {
+            let animation: Animation<u64> = Animation::from_png(self)?;
+            best(&trials, |options| animation.to_png_with(options))?
+        } else {
+            let img: Img<u64> = Img::try_from(self.clone())?;
+            best(&trials, |options| Png::from_img_with(img.clone(), options))?
+        };
+
+        Ok(self.with_chunks_from(encoded, strip))
+    }
+
+    fn trials() -> Vec<EncodeOptions> {
+        let filters = Filter::ALL
+            .into_iter()
+            .map(FilterStrategy::Fixed)
+            .chain([FilterStrategy::MinSum]);
+
+        let strategies = [
+            CompressionStrategy::Default,
+            CompressionStrategy::Filtered,
+            CompressionStrategy::Rle,
+        ];
+
+        filters
+            .flat_map(|filter| {
+                strategies.map(|strategy| {
+                    EncodeOptions::new()
+                        .filter(filter)
+                        .strategy(strategy)
+                        .level(9)
+                        .idat_size(IDAT_SIZE)
+                })
+            })
+            .collect()
+    }
+
+    /// Copy the chunks the encoder doesn't write from `self` into
+    /// `encoded`, those before the image data going before it again.
+    fn with_chunks_from(&self, encoded: Png, strip: bool) -> Png {
+        let same_format = ["IHDR", "PLTE"].iter().all(|name| {
+            self.chunk_by_type(name).map(Chunk::data)
+                == encoded.chunk_by_type(name).map(Chunk::data)
+        });
+
+        let keep = |chunk: &&Chunk| {
+            let name = chunk.chunk_type().to_string();
+            !strip
+                && !REGENERATED.contains(&name.as_str())
+                && (same_format || !FORMAT_DEPENDENT.contains(&name.as_str()))
+        };
+
+        let is_data =
+            |chunk: &Chunk| ["IDAT", "fcTL"].contains(&chunk.chunk_type().to_string().as_str());
+        let split = self.chunks().iter().position(is_data).unwrap_or(0);
+        let (before, after) = self.chunks().split_at(split);
+
+        let mut chunks = encoded.chunks().to_vec();
+        let iend = chunks.pop();
+        let data = chunks.iter().position(is_data).unwrap_or(chunks.len());
+
+        chunks.splice(data..data, before.iter().filter(keep).cloned());
+        chunks.extend(after.iter().filter(keep).cloned());
+        chunks.extend(iend);
+        Png::from_chunks(chunks)
+    }
+}
+
+/// Run `encode` with each of `trials` across the available cores and keep
+/// the smallest result.
+fn best<F>(trials: &[EncodeOptions], encode: F) -> PngRes<Png>
+where
+    F: Fn(&EncodeOptions) -> PngRes<Png> + Sync,
+{
+    let threads = std::thread::available_parallelism().map_or(1, usize::from);
+    let encode = &encode;
+    let mut best: Option<(usize, Png)> = None;
+
+    for group in trials.chunks(threads) {
+        let results = std::thread::scope(|scope| {
+            let handles: Vec<_> = group
+                .iter()
+                .map(|options| scope.spawn(move || encode(options)))
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().map_err(|_| PngErr::CompressError)?)
+                .collect::<PngRes<Vec<_>>>()
+        })?;
+
+        for png in results {
+            let size = png.as_bytes().len();
+            if best.as_ref().is_none_or(|(smallest, _)| size < *smallest) {
+                best = Some((size, png));
+            }
+        }
+    }
+
+    best.map(|(_, png)| png).ok_or(PngErr::CompressError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::{ChunkType, Frame, Message};
+    use std::str::FromStr;
+
+    fn message(chunk_type: &str, text: &str) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), text.into())
+    }
+
+    /// A bloated file: a gray image stored as RGBA in tiny stored IDATs.
+    fn bloated() -> Png {
+        let img: Img = Img::from_vec(
+            64,
+            64,
+            (0..64 * 64)
+                .map(|i| ((i % 64 * 4) * 0x01010100) | 0xFF)
+                .collect(),
+        );
+
+        let options = EncodeOptions::new().reduce(false).level(0).idat_size(1000);
+        let png = Png::from_img_with(img, &options).unwrap();
+
+        let mut chunks = png.chunks().to_vec();
+        chunks.insert(1, message("tEXt", "Comment\0before"));
+        chunks.insert(2, message("bKGD", "\0\0\0\0\0\0"));
+        chunks.insert(chunks.len() - 1, message("ruSt", "after"));
+        Png::from_chunks(chunks)
+    }
+
+    fn names(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_optimize_is_smaller_and_lossless() {
+        let png = bloated();
+        let optimized = png.optimize(false).unwrap();
+
+        assert!(optimized.as_bytes().len() < png.as_bytes().len() / 4);
+        assert_eq!(
+            optimized.clone().to_img().unwrap().data(),
+            png.clone().to_img().unwrap().data()
+        );
+
+        // Reduced to grayscale, so the RGB background no longer applies.
+        assert_eq!(names(&optimized), ["IHDR", "tEXt", "IDAT", "ruSt", "IEND"]);
+        assert_eq!(
+            optimized.decode_nth("ruSt", 0).unwrap(),
+            Message::from("after")
+        );
+    }
+
+    #[test]
+    fn test_optimize_strip() {
+        let mut png = bloated();
+        let gamma = ChunkType::from_str("gAMA").unwrap();
+        png.append_chunk(Chunk::new(gamma, vec![0, 0, 0xB1, 0x8F]))
+            .unwrap();
+
+        let optimized = png.optimize(true).unwrap();
+        assert_eq!(names(&optimized), ["IHDR", "gAMA", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_optimize_animation() {
+        let still = bloated().to_img().unwrap();
+
+        let mut animation = Animation::new(64, 64);
+        animation.frames = vec![Frame::new(still.clone(), 100), Frame::new(still, 200)];
+
+        let png = animation
+            .to_png_with(&EncodeOptions::new().level(1))
+            .unwrap();
+        let optimized = png.optimize(false).unwrap();
+        let decoded: Animation = Animation::from_png(&optimized).unwrap();
+
+        assert!(optimized.as_bytes().len() <= png.as_bytes().len());
+        assert_eq!(decoded.frames.len(), 2);
+        assert_eq!(decoded.frames[1].delay_num, 200);
+        assert_eq!(decoded.frames[1].img.data(), animation.frames[1].img.data());
+    }
+}
@@ -0,0 +1,82 @@
+use super::{CompressionStrategy, Deflate, FilterStrategy, Png};
+
+/// Settings used when converting an `Img` to a `Png`.
+#[derive(Debug, Clone)]
+pub struct EncodeOptions {
+    pub filter: FilterStrategy,
+    pub interlace: bool,
+    /// Write the smallest color type and bit depth which is lossless.
+    pub reduce: bool,
+    pub deflate: Deflate,
+    /// Largest IDAT chunk to write before starting another.
+    pub idat_size: usize,
+    /// Threads used to filter and compress bands of rows, or 0 for one per
+    /// core. Interlaced images are always encoded on one thread.
+    pub threads: usize,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            filter: FilterStrategy::default(),
+            interlace: false,
+            reduce: true,
+            deflate: Deflate::default(),
+            idat_size: Png::CHUNK_SIZE,
+            threads: 1,
+        }
+    }
+}
+
+impl EncodeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn filter(mut self, filter: FilterStrategy) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn interlace(mut self, interlace: bool) -> Self {
+        self.interlace = interlace;
+        self
+    }
+
+    pub fn reduce(mut self, reduce: bool) -> Self {
+        self.reduce = reduce;
+        self
+    }
+
+    pub fn level(mut self, level: u8) -> Self {
+        self.deflate.level = level;
+        self
+    }
+
+    pub fn strategy(mut self, strategy: CompressionStrategy) -> Self {
+        self.deflate.strategy = strategy;
+        self
+    }
+
+    pub fn window(mut self, window: u8) -> Self {
+        self.deflate.window = window;
+        self
+    }
+
+    pub fn idat_size(mut self, idat_size: usize) -> Self {
+        self.idat_size = idat_size;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+}
+
+/// Settings used when converting a `Png` to an `Img`.
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    /// Convert pixels to sRGB using the gAMA and cHRM chunks.
+    pub srgb: bool,
+}
@@ -0,0 +1,154 @@
+use super::{Chunk, ColorType};
+use crate::{col, err::*};
+
+/// The entries of a PLTE chunk as RGBA colors, with alpha taken from tRNS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    colors: Vec<u32>,
+}
+
+impl Palette {
+    pub const MAX_ENTRIES: usize = 256;
+
+    pub fn new(colors: Vec<u32>) -> PngRes<Self> {
+        PngErr::not_or(
+            colors.is_empty() || colors.len() > Self::MAX_ENTRIES,
+            PngErr::InvalidPalette,
+        )?;
+
+        Ok(Self { colors })
+    }
+
+    pub fn from_chunks(plte: &Chunk, trns: Option<&Chunk>) -> PngRes<Self> {
+        let data = plte.data();
+        PngErr::not_or(!data.len().is_multiple_of(3), PngErr::InvalidPalette)?;
+
+        let alpha = trns.map_or(&[][..], |trns| trns.data());
+        PngErr::not_or(alpha.len() > data.len() / 3, PngErr::InvalidTransparency)?;
+
+        Self::new(
+            data.chunks(3)
+                .enumerate()
+                .map(|(i, rgb)| col!(rgb[0], rgb[1], rgb[2], *alpha.get(i).unwrap_or(&255)))
+                .collect(),
+        )
+    }
+
+    pub fn colors(&self) -> &[u32] {
+        &self.colors
+    }
+
+    pub fn get(&self, idx: usize) -> Option<u32> {
+        self.colors.get(idx).copied()
+    }
+
+    /// Alpha values for a tRNS chunk, omitting trailing opaque entries.
+    pub fn alpha(&self) -> Vec<u8> {
+        let len = self
+            .colors
+            .iter()
+            .rposition(|px| px & 0xFF != 0xFF)
+            .map_or(0, |pos| pos + 1);
+
+        self.colors[..len]
+            .iter()
+            .map(|px| px.to_be_bytes()[3])
+            .collect()
+    }
+}
+
+/// The contents of a tRNS chunk, which depend on the image color type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trns {
+    /// Alpha for each palette entry, in order.
+    Palette(Vec<u8>),
+    /// A gray level which should be treated as fully transparent.
+    Gray(u16),
+    /// An RGB color which should be treated as fully transparent.
+    Rgb(u16, u16, u16),
+}
+
+impl Trns {
+    pub fn from_chunk(chunk: &Chunk, color_type: ColorType) -> PngRes<Self> {
+        let data = chunk.data();
+        let sample = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+
+        match color_type {
+            ColorType::Indexed => Ok(Trns::Palette(data.to_vec())),
+            ColorType::Grayscale if data.len() == 2 => Ok(Trns::Gray(sample(0))),
+            ColorType::Rgb if data.len() == 6 => Ok(Trns::Rgb(sample(0), sample(2), sample(4))),
+            _ => Err(PngErr::InvalidTransparency),
+        }
+    }
+
+    pub fn data(&self) -> Vec<u8> {
+        match self {
+            Trns::Palette(alpha) => alpha.clone(),
+            Trns::Gray(v) => v.to_be_bytes().to_vec(),
+            Trns::Rgb(r, g, b) => [r, g, b].iter().flat_map(|s| s.to_be_bytes()).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn palette() -> Palette {
+        Palette::new(vec![0xFF000080, 0x00FF0000, 0x0000FFFF]).unwrap()
+    }
+
+    #[test]
+    fn test_palette_chunks() {
+        let palette = palette();
+        let plte = Chunk::plte(&palette).unwrap();
+        let trns = Chunk::trns(&Trns::Palette(palette.alpha())).unwrap();
+
+        assert_eq!(plte.chunk_type().to_string(), "PLTE");
+        assert_eq!(plte.data(), [255, 0, 0, 0, 255, 0, 0, 0, 255]);
+        assert_eq!(trns.chunk_type().to_string(), "tRNS");
+        assert_eq!(trns.data(), [0x80, 0]);
+
+        assert_eq!(Palette::from_chunks(&plte, Some(&trns)).unwrap(), palette);
+    }
+
+    #[test]
+    fn test_palette_without_trns() {
+        let plte = Chunk::plte(&palette()).unwrap();
+        let opaque = Palette::from_chunks(&plte, None).unwrap();
+
+        assert!(opaque.alpha().is_empty());
+        assert_eq!(opaque.get(1), Some(0x00FF00FF));
+        assert_eq!(opaque.get(3), None);
+    }
+
+    #[test]
+    fn test_invalid_palettes() {
+        assert!(Palette::new(Vec::new()).is_err());
+        assert!(Palette::new(vec![0; 257]).is_err());
+
+        let plte = Chunk::plte(&palette()).unwrap();
+        let trns = Chunk::trns(&Trns::Palette(vec![0; 4])).unwrap();
+        assert!(Palette::from_chunks(&plte, Some(&trns)).is_err());
+    }
+
+    #[test]
+    fn test_trns_color_types() {
+        let gray = Chunk::trns(&Trns::Gray(0x1234)).unwrap();
+        assert_eq!(gray.data(), [0x12, 0x34]);
+        assert_eq!(
+            Trns::from_chunk(&gray, ColorType::Grayscale).unwrap(),
+            Trns::Gray(0x1234)
+        );
+
+        let rgb = Chunk::trns(&Trns::Rgb(1, 2, 3)).unwrap();
+        assert_eq!(rgb.data(), [0, 1, 0, 2, 0, 3]);
+        assert_eq!(
+            Trns::from_chunk(&rgb, ColorType::Rgb).unwrap(),
+            Trns::Rgb(1, 2, 3)
+        );
+
+        assert!(Trns::from_chunk(&gray, ColorType::Rgb).is_err());
+        assert!(Trns::from_chunk(&gray, ColorType::Rgba).is_err());
+    }
+}
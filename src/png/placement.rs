@@ -0,0 +1,191 @@
+use super::AsChunkRef;
+use crate::err::*;
+use std::str::FromStr;
+
+/// Where `Png::encode` and `Png::insert_chunk` put a new chunk.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Placement {
+    /// Last before IEND, where decoders expect ancillary chunks to end.
+    #[default]
+    BeforeIend,
+    /// Before the first chunk of image data, so it is read before any of
+    /// the pixels.
+    BeforeIdat,
+    AfterIhdr,
+    /// At this index, which must be after IHDR and no later than IEND.
+    Index(usize),
+}
+
+impl Placement {
+    /// The index at which a new chunk goes among `chunks`.
+    pub fn index<C: AsChunkRef>(&self, chunks: &[C]) -> PngRes<usize> {
+        let position = |names: &[&str]| {
+            chunks.iter().position(|chunk| {
+                names.contains(&chunk.as_chunk_ref().chunk_type().to_string().as_str())
+            })
+        };
+
+        let ihdr = position(&["IHDR"]);
+        let iend = position(&["IEND"]).unwrap_or(chunks.len());
+
+        Ok(match *self {
+            Placement::BeforeIend => iend,
+            Placement::BeforeIdat => position(&["IDAT", "fcTL", "fdAT"]).unwrap_or(iend),
+            Placement::AfterIhdr => ihdr.map_or(0, |ihdr| ihdr + 1),
+            Placement::Index(index) => {
+                PngErr::is_or(
+                    ihdr.is_none_or(|ihdr| index > ihdr) && index <= iend,
+                    PngErr::InvalidPlacement,
+                )?;
+                index
+            }
+        })
+    }
+}
+
+impl FromStr for Placement {
+    type Err = PngErr;
+    fn from_str(placement: &str) -> PngRes<Self> {
+        Ok(match placement {
+            "before-iend" => Placement::BeforeIend,
+            "before-idat" => Placement::BeforeIdat,
+            "after-ihdr" => Placement::AfterIhdr,
+            _ => Placement::Index(placement.parse().map_err(|_| PngErr::InvalidOption)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::{Chunk, ChunkType, Message, Png};
+
+    fn png() -> Png {
+        let chunk = |name: &str| Chunk::new(ChunkType::from_str(name).unwrap(), Vec::new());
+        Png::from_chunks(["IHDR", "gAMA", "IDAT", "IDAT", "IEND"].map(chunk).to_vec())
+    }
+
+    fn names(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_encode_placement() {
+        let mut png = png();
+        png.encode("ruSt", "end").unwrap();
+        assert_eq!(names(&png)[4..], ["ruSt", "IEND"]);
+
+        png.set_placement(Placement::BeforeIdat)
+            .encode("ruSt", "replaced")
+            .unwrap();
+        assert_eq!(names(&png)[4..], ["ruSt", "IEND"]);
+        assert_eq!(
+            png.decode_nth("ruSt", 0).unwrap(),
+            Message::from("replaced")
+        );
+
+        png.discard("ruSt")
+            .unwrap()
+            .encode("ruSt", "moved")
+            .unwrap();
+        assert_eq!(
+            names(&png),
+            ["IHDR", "gAMA", "ruSt", "IDAT", "IDAT", "IEND"]
+        );
+        assert_eq!(png.decode_nth("ruSt", 0).unwrap(), Message::from("moved"));
+
+        png.set_placement(Placement::AfterIhdr)
+            .encode("teSt", "first")
+            .unwrap();
+        assert_eq!(names(&png)[..3], ["IHDR", "teSt", "gAMA"]);
+
+        png.set_placement(Placement::Index(3))
+            .encode_nth("teSt", "second", 1)
+            .unwrap();
+        assert_eq!(names(&png)[..4], ["IHDR", "teSt", "gAMA", "teSt"]);
+        assert_eq!(
+            png.decode_all("teSt").unwrap(),
+            ["first", "second"].map(Message::from)
+        );
+    }
+
+    #[test]
+    fn test_invalid_index() {
+        let png = png();
+        assert_eq!(Placement::Index(1).index(png.chunks()), Ok(1));
+        assert_eq!(Placement::Index(4).index(png.chunks()), Ok(4));
+        assert_eq!(
+            Placement::Index(0).index(png.chunks()),
+            Err(PngErr::InvalidPlacement)
+        );
+        assert_eq!(
+            Placement::Index(5).index(png.chunks()),
+            Err(PngErr::InvalidPlacement)
+        );
+
+        let mut png = png;
+        png.encode("ruSt", "kept").unwrap();
+        let before = names(&png);
+        assert!(png
+            .set_placement(Placement::Index(9))
+            .encode_nth("ruSt", "lost", 1)
+            .is_err());
+        assert_eq!(names(&png), before);
+        assert_eq!(png.decode_nth("ruSt", 0).unwrap(), Message::from("kept"));
+    }
+
+    #[test]
+    fn test_append_stays_before_iend() {
+        let mut png = png();
+        assert!(png.append_chunk(Chunk::iend().unwrap()).is_err());
+        assert!(png.append_chunk(png.chunks()[0].clone()).is_err());
+        png.append_chunk(Chunk::idat(&[]).unwrap()).unwrap();
+        assert_eq!(names(&png)[3..], ["IDAT", "IDAT", "IEND"]);
+
+        png.encode("ruSt", "after").unwrap();
+        assert_eq!(
+            png.append_chunk(Chunk::idat(&[]).unwrap()).unwrap_err(),
+            PngErr::InvalidPlacement
+        );
+        assert_eq!(names(&png)[5..], ["ruSt", "IEND"]);
+
+        assert_eq!(
+            Placement::from_str("before-idat"),
+            Ok(Placement::BeforeIdat)
+        );
+        assert_eq!(Placement::from_str("3"), Ok(Placement::Index(3)));
+        assert!(Placement::from_str("after-iend").is_err());
+    }
+
+    #[test]
+    fn test_insert_keeps_order() {
+        let mut png = png();
+        let idat = || Chunk::idat(&[]).unwrap();
+        for placement in [
+            Placement::AfterIhdr,
+            Placement::Index(0),
+            Placement::Index(4),
+        ] {
+            assert!(png.insert_chunk(Chunk::iend().unwrap(), placement).is_err());
+            assert!(png
+                .insert_chunk(png.chunks()[0].clone(), placement)
+                .is_err());
+        }
+        assert_eq!(
+            png.insert_chunk(idat(), Placement::AfterIhdr).unwrap_err(),
+            PngErr::InvalidPlacement
+        );
+        png.insert_chunk(idat(), Placement::BeforeIdat)
+            .unwrap()
+            .insert_chunk(idat(), Placement::Index(3))
+            .unwrap();
+        assert_eq!(names(&png)[2..], ["IDAT", "IDAT", "IDAT", "IDAT", "IEND"]);
+
+        let mut bare = Png::from_chunks(vec![png.chunks()[0].clone(), Chunk::iend().unwrap()]);
+        bare.insert_chunk(idat(), Placement::AfterIhdr).unwrap();
+        assert_eq!(names(&bare), ["IHDR", "IDAT", "IEND"]);
+    }
+}
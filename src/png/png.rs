@@ -1,94 +1,231 @@
+use super::split;
 use super::{
-    chunk::{Chunk, ChunkIter},
-    ChunkType,
+    AsChunkRef, Chunk, ChunkReader, ChunkRef, ChunkSink, ChunkType, ChunkWriter, EncodeOptions,
+    ImageWriter, Message, PixelFormat, Placement,
+};
+use crate::{err::*, fs, img::Img, Color, Quad, INT_MAX};
+use std::{
+    fmt,
+    io::{Read, Write},
+    str::FromStr,
 };
-use crate::{err::*, fs, img::Img, Quad};
-use std::{fmt, str::FromStr};
 
+/// A PNG as its list of chunks. These are owned unless it was made by
+/// `Png::borrowed`, whose chunks point into the file's bytes instead.
 #[derive(Debug, Clone)]
-pub struct Png {
-    chunks: Vec<Chunk>,
+pub struct Png<C = Chunk> {
+    chunks: Vec<C>,
+    placement: Placement,
 }
 
 impl Png {
-    const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
-    const CHUNK_SIZE: usize = 16384;
+    pub(super) const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+    pub(super) const CHUNK_SIZE: usize = 16384;
 
     pub fn new() -> Self {
-        Self { chunks: Vec::new() }
+        Self::from_chunks(Vec::new())
     }
 
     pub fn load(filename: &str) -> PngRes<Self> {
-        Self::try_from(fs::read(filename)?.as_slice())
+        Self::from_reader(fs::open(filename)?)
     }
 
-    pub fn save(&self, filename: &str) -> PngRes {
-        fs::write(filename, self.as_bytes().as_slice())
+    /// Read a complete PNG from `reader`. Use `ChunkReader` directly to
+    /// process the chunks one at a time instead.
+    pub fn from_reader(reader: impl Read) -> PngRes<Self> {
+        Ok(Self::from_chunks(
+            ChunkReader::png(reader)?.collect::<PngRes<_>>()?,
+        ))
     }
 
     pub fn from_chunks(chunks: Vec<Chunk>) -> Self {
-        Self { chunks }
+        Self {
+            chunks,
+            placement: Placement::default(),
+        }
+    }
+
+    /// Add `chunk` after the others, though still before IEND.
+    pub fn append_chunk(&mut self, chunk: Chunk) -> PngRes<&mut Self> {
+        self.insert_chunk(chunk, Placement::BeforeIend)
     }
 
-    pub fn append_chunk(&mut self, chunk: Chunk) {
+    /// Add `chunk` at the very end, as when building a file in stream
+    /// order.
+    pub(super) fn push_chunk(&mut self, chunk: Chunk) {
         self.chunks.push(chunk)
     }
 
-    pub fn remove_chunk(&mut self, chunk_type: &str) -> PngRes<Chunk> {
-        self.chunks
-            .iter()
-            .position(|chunk| chunk.chunk_type().to_string() == chunk_type)
-            .map_or_else(
-                || Err(PngErr::ChunkNotFound),
-                |pos| Ok(self.chunks.remove(pos)),
-            )
+    /// Add `chunk` where `placement` says. IHDR and IEND can't be added,
+    /// nor IDAT unless it is next to another IDAT, as any of them would
+    /// leave the chunks out of order.
+    pub fn insert_chunk(&mut self, chunk: Chunk, placement: Placement) -> PngRes<&mut Self> {
+        let index = placement.index(&self.chunks)?;
+        let name = chunk.chunk_type().to_string();
+        let idat_at = |at: Option<usize>| {
+            at.and_then(|at| self.chunks.get(at))
+                .is_some_and(|chunk| is_type(chunk, "IDAT"))
+        };
+        let idat_beside = idat_at(index.checked_sub(1)) || idat_at(Some(index));
+        PngErr::not_or(
+            name == "IHDR"
+                || name == "IEND"
+                || (name == "IDAT" && !idat_beside && self.chunk_by_type("IDAT").is_some()),
+            PngErr::InvalidPlacement,
+        )?;
+
+        self.chunks.insert(index, chunk);
+        Ok(self)
     }
 
-    pub fn header(&self) -> [u8; 8] {
-        Self::STANDARD_HEADER
+    /// Set where `encode` puts message chunks.
+    pub fn set_placement(&mut self, placement: Placement) -> &mut Self {
+        self.placement = placement;
+        self
     }
 
-    pub fn chunks(&self) -> &[Chunk] {
-        &self.chunks
+    pub fn remove_chunk(&mut self, chunk_type: &str) -> PngRes<Chunk> {
+        self.remove_nth_chunk(chunk_type, 0)
     }
 
-    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
-        self.chunks
-            .iter()
-            .find(|&chunk| chunk.chunk_type().to_string() == chunk_type)
+    /// Remove the `index`th chunk of `chunk_type`, counting from zero.
+    pub fn remove_nth_chunk(&mut self, chunk_type: &str, index: usize) -> PngRes<Chunk> {
+        self.position(chunk_type, index).map_or_else(
+            || Err(PngErr::ChunkNotFound),
+            |pos| Ok(self.chunks.remove(pos)),
+        )
     }
 
-    pub fn as_bytes(&self) -> Vec<u8> {
-        Self::STANDARD_HEADER
+    pub fn remove_chunks(&mut self, chunk_type: &str) -> PngRes<Vec<Chunk>> {
+        let (removed, kept) = std::mem::take(&mut self.chunks)
             .into_iter()
-            .chain(self.chunks.iter().flat_map(|chunk| chunk.as_bytes()))
-            .collect()
+            .partition(|chunk| is_type(chunk, chunk_type));
+        self.chunks = kept;
+
+        PngErr::not_or(removed.is_empty(), PngErr::ChunkNotFound)?;
+        Ok(removed)
     }
 
     pub fn scrub(&mut self) -> &mut Self {
-        self.chunks.retain(|chunk| chunk.chunk_type().is_critical());
+        self.chunks.retain(|chunk| {
+            let chunk_type = chunk.chunk_type();
+            chunk_type.is_critical() || chunk_type.is_animation()
+        });
         self
     }
 
     pub fn encode(&mut self, chunk_type: &str, message: &str) -> PngRes<&mut Self> {
+        self.encode_nth(chunk_type, message, 0)
+    }
+
+    pub fn encode_bytes(&mut self, chunk_type: &str, data: &[u8]) -> PngRes<&mut Self> {
+        self.encode_nth(chunk_type, data, 0)
+    }
+
+    pub fn encode_nth(
+        &mut self,
+        chunk_type: &str,
+        message: impl Into<Message>,
+        index: usize,
+    ) -> PngRes<&mut Self> {
         ChunkType::from_str(chunk_type)?.checked_me_type()?;
-        self.encode_unchecked(chunk_type, message)
+        self.encode_nth_unchecked(chunk_type, message, index)
     }
 
     pub fn encode_unchecked(&mut self, chunk_type: &str, message: &str) -> PngRes<&mut Self> {
-        let chunk = Chunk::new(
-            ChunkType::from_str(chunk_type)?,
-            message.as_bytes().to_vec(),
-        );
+        self.encode_nth_unchecked(chunk_type, message, 0)
+    }
+
+    /// Replace the `index`th chunk of the type with a message chunk, which
+    /// takes its place. An index one past the last chunk of that type adds
+    /// another where the placement policy says.
+    pub fn encode_nth_unchecked(
+        &mut self,
+        chunk_type: &str,
+        message: impl Into<Message>,
+        index: usize,
+    ) -> PngRes<&mut Self> {
+        let message = message.into().to_bytes();
+        PngErr::not_or(message.len() >= INT_MAX as usize, PngErr::MessageTooLarge)?;
+        let chunk = Chunk::new(ChunkType::from_str(chunk_type)?, message);
+
+        let count = self.chunks_by_type(chunk_type).len();
+        PngErr::not_or(index > count, PngErr::ChunkNotFound)?;
 
-        self.remove_chunk(chunk_type).ok();
-        self.append_chunk(chunk);
+        match self.position(chunk_type, index) {
+            Some(pos) => self.chunks[pos] = chunk,
+            None => self.replace_chunks(|_, _| false, vec![chunk])?,
+        }
         Ok(self)
     }
 
+    pub fn encode_split(
+        &mut self,
+        chunk_type: &str,
+        message: impl Into<Message>,
+        size: usize,
+    ) -> PngRes<&mut Self> {
+        ChunkType::from_str(chunk_type)?.checked_me_type()?;
+        self.encode_split_unchecked(chunk_type, message, size)
+    }
+
+    /// Replace every chunk of `chunk_type` with `message` split into parts
+    /// of at most `size` bytes, which `decode` puts back together.
+    pub fn encode_split_unchecked(
+        &mut self,
+        chunk_type: &str,
+        message: impl Into<Message>,
+        size: usize,
+    ) -> PngRes<&mut Self> {
+        let chunk_type = ChunkType::from_str(chunk_type)?;
+        PngErr::not_or(size >= INT_MAX as usize, PngErr::InvalidOption)?;
+        let parts = split::split(&message.into().to_bytes(), size)?
+            .into_iter()
+            .map(|part| Chunk::new(chunk_type, part))
+            .collect();
+
+        self.replace_chunks(|_, chunk| *chunk.chunk_type() == chunk_type, parts)?;
+        Ok(self)
+    }
+
+    /// Remove the chunks `remove` picks out by position, then put `chunks`
+    /// among the rest where the placement policy says. Nothing changes if
+    /// the placement is invalid.
+    pub(super) fn replace_chunks(
+        &mut self,
+        remove: impl Fn(usize, &Chunk) -> bool,
+        chunks: Vec<Chunk>,
+    ) -> PngRes {
+        let kept: Vec<&Chunk> = self
+            .chunks
+            .iter()
+            .enumerate()
+            .filter(|(pos, chunk)| !remove(*pos, chunk))
+            .map(|(_, chunk)| chunk)
+            .collect();
+        let index = self.placement.index(&kept)?;
+
+        let mut pos = 0;
+        self.chunks.retain(|chunk| {
+            pos += 1;
+            !remove(pos - 1, chunk)
+        });
+        self.chunks.splice(index..index, chunks);
+        Ok(())
+    }
+
     pub fn discard(&mut self, chunk_type: &str) -> PngRes<&mut Self> {
+        self.discard_nth(chunk_type, 0)
+    }
+
+    pub fn discard_nth(&mut self, chunk_type: &str, index: usize) -> PngRes<&mut Self> {
         ChunkType::from_str(chunk_type)?.checked_me_type()?;
-        self.discard_unchecked(chunk_type)
+        self.discard_nth_unchecked(chunk_type, index)
+    }
+
+    pub fn discard_all(&mut self, chunk_type: &str) -> PngRes<&mut Self> {
+        ChunkType::from_str(chunk_type)?.checked_me_type()?;
+        self.discard_all_unchecked(chunk_type)
     }
 
     pub fn discard_unchecked(&mut self, chunk_type: &str) -> PngRes<&mut Self> {
@@ -96,57 +233,232 @@ impl Png {
         Ok(self)
     }
 
-    pub fn decode(&self, chunk_type: &str) -> PngRes<String> {
-        self.chunk_by_type(chunk_type).map_or_else(
-            || Err(PngErr::ChunkNotFound),
-            |chunk| chunk.data_as_string(),
-        )
+    pub fn discard_nth_unchecked(&mut self, chunk_type: &str, index: usize) -> PngRes<&mut Self> {
+        self.remove_nth_chunk(chunk_type, index)?;
+        Ok(self)
+    }
+
+    pub fn discard_all_unchecked(&mut self, chunk_type: &str) -> PngRes<&mut Self> {
+        self.remove_chunks(chunk_type)?;
+        Ok(self)
     }
 
     pub fn to_img(self) -> PngRes<Img> {
         self.try_into()
     }
 
-    pub fn from_img(img: Img) -> PngRes<Self> {
+    pub fn from_img<P: Color>(img: Img<P>) -> PngRes<Self> {
         img.try_into()
     }
+
+    pub fn from_img_with<P: Color>(img: Img<P>, options: &EncodeOptions) -> PngRes<Self> {
+        Self::encode_img(&img, Self::new(), options, Vec::new())
+    }
+
+    /// Encode `img` straight to `writer`, compressing it row by row rather
+    /// than building the whole file in memory. `chunks` are written after
+    /// the header, before the image data.
+    pub fn write_img<P: Color, W: Write>(
+        img: &Img<P>,
+        writer: W,
+        options: &EncodeOptions,
+        chunks: Vec<Chunk>,
+    ) -> PngRes<W> {
+        Self::encode_img(img, ChunkWriter::png(writer)?, options, chunks)?.finish()
+    }
+
+    fn encode_img<P: Color, S: ChunkSink>(
+        img: &Img<P>,
+        sink: S,
+        options: &EncodeOptions,
+        chunks: Vec<Chunk>,
+    ) -> PngRes<S> {
+        let (width, height) = img.dimensions();
+        let format = if options.reduce {
+            PixelFormat::analyse(img.data(), width)
+        } else {
+            PixelFormat::rgba::<P>()
+        };
+
+        let mut header = img.color_space().chunks()?;
+        header.extend(chunks);
+
+        let mut writer = ImageWriter::new(sink, width, height, &format, options, header)?;
+        writer.write_img(img)?;
+        writer.finish()
+    }
 }
 
-impl TryFrom<&[u8]> for Png {
-    type Error = PngErr;
-    fn try_from(bytes: &[u8]) -> PngRes<Self> {
-        if bytes.len() < 8 || &bytes[0..8] != &Self::STANDARD_HEADER {
-            return Err(PngErr::InvalidHeader);
+impl<'a> Png<ChunkRef<'a>> {
+    /// Parse a complete PNG without copying any chunk data out of `bytes`.
+    pub fn borrowed(bytes: &'a [u8]) -> PngRes<Self> {
+        PngErr::is_or(
+            bytes.starts_with(&Png::STANDARD_HEADER),
+            PngErr::InvalidHeader,
+        )?;
+
+        let mut chunks = Vec::new();
+        let mut rest = &bytes[Png::STANDARD_HEADER.len()..];
+        while !rest.is_empty() {
+            let (chunk, next) = ChunkRef::split_from(rest)?;
+            chunks.push(chunk);
+            rest = next;
         }
 
         Ok(Self {
-            chunks: ChunkIter::new(&bytes[8..]).collect::<Result<_, _>>()?,
+            chunks,
+            placement: Placement::default(),
         })
     }
 }
 
-impl TryFrom<Img> for Png {
+impl<C: AsChunkRef> Png<C> {
+    pub fn save(&self, filename: &str) -> PngRes {
+        self.write_to(fs::create(filename)?).map(drop)
+    }
+
+    /// Write the signature and each chunk in turn to `writer`.
+    pub fn write_to<W: Write>(&self, writer: W) -> PngRes<W> {
+        let mut writer = ChunkWriter::png(writer)?;
+        for chunk in &self.chunks {
+            writer.write(chunk)?;
+        }
+
+        writer.finish()
+    }
+
+    pub fn header(&self) -> [u8; 8] {
+        Png::STANDARD_HEADER
+    }
+
+    pub fn chunks(&self) -> &[C] {
+        &self.chunks
+    }
+
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&C> {
+        self.nth_chunk_by_type(chunk_type, 0)
+    }
+
+    /// The `index`th chunk of `chunk_type`, counting from zero.
+    pub fn nth_chunk_by_type(&self, chunk_type: &str, index: usize) -> Option<&C> {
+        self.position(chunk_type, index)
+            .map(|pos| &self.chunks[pos])
+    }
+
+    pub fn chunks_by_type(&self, chunk_type: &str) -> Vec<&C> {
+        self.chunks
+            .iter()
+            .filter(|chunk| is_type(*chunk, chunk_type))
+            .collect()
+    }
+
+    /// Where the `index`th chunk of `chunk_type` is in the whole file.
+    fn position(&self, chunk_type: &str, index: usize) -> Option<usize> {
+        self.chunks
+            .iter()
+            .enumerate()
+            .filter(|(_, chunk)| is_type(*chunk, chunk_type))
+            .nth(index)
+            .map(|(pos, _)| pos)
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        Png::STANDARD_HEADER
+            .into_iter()
+            .chain(
+                self.chunks
+                    .iter()
+                    .flat_map(|chunk| chunk.as_chunk_ref().as_bytes()),
+            )
+            .collect()
+    }
+
+    pub fn decode(&self, chunk_type: &str) -> PngRes<String> {
+        String::from_utf8(self.decode_bytes(chunk_type)?).map_err(|_| PngErr::NotUTF8)
+    }
+
+    /// The first message of `chunk_type`, which can't be encrypted.
+    pub fn decode_bytes(&self, chunk_type: &str) -> PngRes<Vec<u8>> {
+        match self.decode_nth(chunk_type, 0)? {
+            Message::Plain(data) => Ok(data),
+            Message::Locked(_) => Err(PngErr::MessageLocked),
+        }
+    }
+
+    /// The `index`th message of `chunk_type`. If its chunk is part of a
+    /// split message, the whole message is put back together.
+    pub fn decode_nth(&self, chunk_type: &str, index: usize) -> PngRes<Message> {
+        let data = self
+            .nth_chunk_by_type(chunk_type, index)
+            .ok_or(PngErr::ChunkNotFound)?
+            .as_chunk_ref()
+            .data();
+
+        if split::is_part(data) {
+            self.join_parts(chunk_type)
+        } else {
+            Message::parse(data)
+        }
+    }
+
+    /// Every message of `chunk_type`, in file order. A split message is
+    /// given once, where its first chunk is.
+    pub fn decode_all(&self, chunk_type: &str) -> PngRes<Vec<Message>> {
+        let chunks = self.chunks_by_type(chunk_type);
+        PngErr::not_or(chunks.is_empty(), PngErr::ChunkNotFound)?;
+
+        let mut joined = false;
+        let mut messages = Vec::new();
+        for data in chunks.iter().map(|chunk| chunk.as_chunk_ref().data()) {
+            if !split::is_part(data) {
+                messages.push(Message::parse(data)?);
+            } else if !joined {
+                messages.push(self.join_parts(chunk_type)?);
+                joined = true;
+            }
+        }
+
+        Ok(messages)
+    }
+
+    fn join_parts(&self, chunk_type: &str) -> PngRes<Message> {
+        Message::parse(&split::join(
+            self.chunks_by_type(chunk_type)
+                .into_iter()
+                .map(|chunk| chunk.as_chunk_ref().data())
+                .filter(|data| split::is_part(data)),
+        )?)
+    }
+}
+
+fn is_type(chunk: &impl AsChunkRef, chunk_type: &str) -> bool {
+    chunk.as_chunk_ref().chunk_type().to_string() == chunk_type
+}
+
+impl From<Png<ChunkRef<'_>>> for Png {
+    fn from(png: Png<ChunkRef>) -> Self {
+        Self::from_chunks(png.chunks.into_iter().map(Chunk::from).collect())
+    }
+}
+
+impl TryFrom<&[u8]> for Png {
     type Error = PngErr;
-    fn try_from(img: Img) -> PngRes<Self> {
-        let mut chunks = vec![Chunk::ihdr(img.width(), img.height())?];
-
-        chunks.extend(
-            fs::compress(img.to_bytes().as_slice())?
-                .chunks(Self::CHUNK_SIZE)
-                .map(|data| Chunk::idat(data))
-                .collect::<Result<Vec<Chunk>, PngErr>>()?
-                .into_iter(),
-        );
+    fn try_from(bytes: &[u8]) -> PngRes<Self> {
+        Self::from_reader(bytes)
+    }
+}
 
-        chunks.push(Chunk::iend()?);
-        Ok(Self::from_chunks(chunks))
+impl<P: Color> TryFrom<Img<P>> for Png {
+    type Error = PngErr;
+    fn try_from(img: Img<P>) -> PngRes<Self> {
+        Self::from_img_with(img, &EncodeOptions::default())
     }
 }
 
-impl fmt::Display for Png {
+impl<C: AsChunkRef> fmt::Display for Png<C> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for (index, chunk) in self.chunks().iter().enumerate() {
-            write!(f, "{}: {}\n", index, chunk)?;
+            write!(f, "{}: {}\n", index, chunk.as_chunk_ref())?;
         }
         Ok(())
     }
@@ -156,7 +468,7 @@ impl fmt::Display for Png {
 mod tests {
     use super::*;
     use crate::png::chunk::Chunk;
-    use crate::png::chunk_type::ChunkType;
+    use crate::png::{EncodeOptions, Ihdr};
     use std::convert::TryFrom;
 
     fn testing_chunks() -> Vec<Chunk> {
@@ -267,7 +579,8 @@ mod tests {
     #[test]
     fn test_append_chunk() {
         let mut png = testing_png();
-        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap())
+            .unwrap();
         let chunk = png.chunk_by_type("TeSt").unwrap();
         assert_eq!(&chunk.chunk_type().to_string(), "TeSt");
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
@@ -276,12 +589,136 @@ mod tests {
     #[test]
     fn test_remove_chunk() {
         let mut png = testing_png();
-        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap())
+            .unwrap();
         png.remove_chunk("TeSt").unwrap();
         let chunk = png.chunk_by_type("TeSt");
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_several_chunks_of_a_type() {
+        let mut png = testing_png();
+        png.encode("ruSt", "one").unwrap();
+        png.encode_nth("ruSt", "two", 1).unwrap();
+        png.encode_nth("ruSt", "three", 2).unwrap();
+        assert!(png.encode_nth("ruSt", "five", 4).is_err());
+
+        assert_eq!(png.chunks_by_type("ruSt").len(), 3);
+        assert_eq!(png.chunks_by_type("ruSt")[0].to_string(), "ruSt (4): one");
+        assert_eq!(png.decode_nth("ruSt", 1).unwrap(), Message::from("two"));
+        assert_eq!(
+            png.decode_all("ruSt").unwrap(),
+            ["one", "two", "three"].map(Message::from)
+        );
+        assert!(png.decode_nth("ruSt", 3).is_err());
+
+        png.encode_nth("ruSt", "TWO", 1).unwrap();
+        assert_eq!(
+            png.decode_all("ruSt").unwrap(),
+            ["one", "TWO", "three"].map(Message::from)
+        );
+
+        png.discard_nth("ruSt", 0).unwrap();
+        assert_eq!(png.decode_nth("ruSt", 0).unwrap(), Message::from("TWO"));
+        assert_eq!(png.remove_chunks("ruSt").unwrap().len(), 2);
+        assert!(png.chunk_by_type("ruSt").is_none());
+        assert!(png.discard_all("ruSt").is_err());
+        assert_eq!(png.chunks().len(), 3);
+
+        assert!(png.discard_all("LASt").is_err());
+        png.discard_all_unchecked("LASt").unwrap();
+        assert_eq!(png.chunks().len(), 2);
+    }
+
+    #[test]
+    fn test_split_message() {
+        let message = "a message long enough to need several parts";
+        let mut png = testing_png();
+        png.encode("ruSt", "replaced").unwrap();
+        png.set_placement(Placement::AfterIhdr)
+            .encode_split("ruSt", message, 25)
+            .unwrap();
+
+        let parts = png.chunks_by_type("ruSt");
+        assert_eq!(parts.len(), 4);
+        assert!(parts.iter().all(|chunk| chunk.length() <= 25));
+        assert_eq!(png.decode_nth("ruSt", 0).unwrap(), Message::from(message));
+        assert_eq!(png.decode_nth("ruSt", 3).unwrap(), Message::from(message));
+
+        png.encode_nth("ruSt", "plain", 4).unwrap();
+        assert_eq!(
+            png.decode_all("ruSt").unwrap(),
+            ["plain", message].map(Message::from)
+        );
+
+        png.discard_nth("ruSt", 2).unwrap();
+        assert_eq!(png.decode_nth("ruSt", 1), Err(PngErr::MissingPart));
+        assert_eq!(
+            png.encode_split("ruSt", message, 10).err(),
+            Some(PngErr::SplitTooSmall)
+        );
+        assert_eq!(
+            png.encode_split("ruSt", message, INT_MAX as usize).err(),
+            Some(PngErr::InvalidOption)
+        );
+    }
+
+    #[test]
+    fn test_binary_message() {
+        let data: Vec<u8> = (0..=255).rev().collect();
+        let mut png = testing_png();
+        png.encode_bytes("ruSt", &data).unwrap();
+
+        assert_eq!(png.decode_bytes("ruSt").unwrap(), data);
+        assert_eq!(png.decode("ruSt"), Err(PngErr::NotUTF8));
+        assert_eq!(
+            png.decode_nth("ruSt", 0).unwrap(),
+            Message::Plain(data.clone())
+        );
+
+        png.encode("ruSt", "text").unwrap();
+        assert_eq!(png.decode("ruSt").unwrap(), "text");
+        assert_eq!(png.decode_bytes("ruSt").unwrap(), b"text");
+
+        png.encode_split("ruSt", data.as_slice(), 100).unwrap();
+        assert_eq!(png.chunks_by_type("ruSt").len(), 3);
+        assert_eq!(png.decode_bytes("ruSt").unwrap(), data);
+        assert_eq!(
+            png.decode_all("ruSt").unwrap(),
+            [Message::Plain(data.clone())]
+        );
+
+        png.encode_nth("ruSt", Message::Locked(data), 0).unwrap();
+        assert_eq!(png.decode_bytes("ruSt"), Err(PngErr::MessageLocked));
+    }
+
+    #[test]
+    fn test_message_that_looks_marked() {
+        let looks_marked = (0xF8..=0xFF)
+            .flat_map(|first: u8| [vec![first], [&[first][..], &[1; 40]].concat()])
+            .chain([
+                b"\xFFPART\0\0\0\0\0\0\0\x01\0\0\0\0".to_vec(),
+                b"\xFFLOCK\0".to_vec(),
+            ]);
+
+        for data in looks_marked {
+            let mut png = testing_png();
+            png.encode_nth("ruSt", data.as_slice(), 0).unwrap();
+            assert_eq!(
+                png.decode_nth("ruSt", 0).unwrap(),
+                Message::Plain(data.clone())
+            );
+
+            let locked = Message::Locked(data.clone());
+            png.encode_nth("ruSt", locked.clone(), 0).unwrap();
+            assert_eq!(png.decode_all("ruSt").unwrap(), [locked]);
+
+            png.encode_split("ruSt", data.as_slice(), 20).unwrap();
+            assert_eq!(png.decode_all("ruSt").unwrap(), [Message::Plain(data)]);
+        }
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
@@ -296,6 +733,26 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_borrowed() {
+        let png = Png::borrowed(&PNG_FILE[..]).unwrap();
+        let idat = png.chunk_by_type("IDAT").unwrap();
+        assert!(PNG_FILE.as_ptr_range().contains(&idat.data().as_ptr()));
+
+        assert_eq!(png.as_bytes(), PNG_FILE);
+        assert_eq!(
+            png.to_string(),
+            Png::try_from(&PNG_FILE[..]).unwrap().to_string()
+        );
+        assert_eq!(Png::<Chunk>::from(png).as_bytes(), PNG_FILE);
+
+        assert!(Png::borrowed(&PNG_FILE[1..]).is_err());
+        assert!(Png::borrowed(&PNG_FILE[..PNG_FILE.len() - 1]).is_err());
+        let mut corrupt = PNG_FILE.to_vec();
+        corrupt[20] ^= 1;
+        assert!(matches!(Png::borrowed(&corrupt), Err(PngErr::CRCMismatch)));
+    }
+
     #[test]
     fn test_png_trait_impls() {
         let chunk_bytes: Vec<u8> = testing_chunks()
@@ -314,6 +771,295 @@ mod tests {
         let _png_string = format!("{}", png);
     }
 
+    #[test]
+    fn test_to_img_unfilters_scanlines() {
+        let img = Png::try_from(&PNG_FILE[..]).unwrap().to_img().unwrap();
+        let bytes: Vec<u8> = img.data().iter().flat_map(|px| px.to_be_bytes()).collect();
+
+        assert_eq!(img.data()[0], 0);
+        assert_eq!(img.data()[25 * 50 + 25], 0xF0F0F0FF);
+        assert_eq!(img.data()[12 * 50 + 30], 0xB2D7B2F1);
+        assert_eq!(crc::crc32::checksum_ieee(&bytes), 3827446951);
+    }
+
+    #[test]
+    fn test_to_img_paeth_fixture() {
+        #[rustfmt::skip]
+        let scanlines = [
+            1, 10, 20, 30, 40, 5, 5, 5, 5,  // Sub
+            4, 5, 5, 5, 5, 250, 0, 0, 0,    // Paeth
+        ];
+
+        let png = Png::from_chunks(vec![
+            Ihdr::new(2, 2).to_chunk().unwrap(),
+            Chunk::idat(&fs::compress(&scanlines).unwrap()).unwrap(),
+            Chunk::iend().unwrap(),
+        ]);
+
+        let img = png.to_img().unwrap();
+        assert_eq!(
+            img.data(),
+            &vec![0x0A141E28, 0x0F19232D, 0x0F19232D, 0x0919232D]
+        );
+    }
+
+    #[test]
+    fn test_to_img_color_types() {
+        use crate::png::{ColorType, Ihdr};
+
+        let png = |bit_depth, color_type, scanlines: &[u8], extra: Vec<Chunk>| {
+            let ihdr = Ihdr {
+                bit_depth,
+                color_type,
+                ..Ihdr::new(2, 2)
+            };
+
+            let mut chunks = vec![ihdr.to_chunk().unwrap()];
+            chunks.extend(extra);
+            chunks.push(Chunk::idat(&fs::compress(scanlines).unwrap()).unwrap());
+            chunks.push(Chunk::iend().unwrap());
+            Png::from_chunks(chunks).to_img().unwrap()
+        };
+
+        let gray = png(
+            1,
+            ColorType::Grayscale,
+            &[0, 0b0100_0000, 2, 0b0100_0000],
+            vec![],
+        );
+        assert_eq!(
+            gray.data(),
+            &vec![0x000000FF, 0xFFFFFFFF, 0xFFFFFFFF, 0x000000FF]
+        );
+
+        let plte = chunk_from_strings("PLTE", "ABCDEF").unwrap();
+        let indexed = png(4, ColorType::Indexed, &[0, 0x10, 0, 0x01], vec![plte]);
+        assert_eq!(
+            indexed.data(),
+            &vec![0x444546FF, 0x414243FF, 0x414243FF, 0x444546FF]
+        );
+
+        #[rustfmt::skip]
+        let rgb = png(16, ColorType::Rgb, &[
+            0, 255, 255, 0, 0, 0, 0, 0, 0, 255, 255, 0, 0,
+            2, 1, 1, 255, 255, 0, 0, 0, 0, 1, 1, 255, 255,
+        ], vec![]);
+        assert_eq!(
+            rgb.data(),
+            &vec![0xFF0000FF, 0x00FF00FF, 0x00FF00FF, 0x0000FFFF]
+        );
+    }
+
+    #[test]
+    fn test_img_round_trip_keeps_filters() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let img = png.to_img().unwrap();
+        let again = Png::from_img(img.clone()).unwrap().to_img().unwrap();
+        assert_eq!(img.data(), again.data());
+    }
+
+    #[test]
+    fn test_from_img_adaptive_filters() {
+        use crate::png::{Filter, FilterStrategy};
+
+        let img = Png::try_from(&PNG_FILE[..]).unwrap().to_img().unwrap();
+        let unfiltered = EncodeOptions::new().filter(FilterStrategy::Fixed(Filter::None));
+
+        let plain = Png::from_img_with(img.clone(), &unfiltered).unwrap();
+        let adaptive = Png::from_img(img.clone()).unwrap();
+        assert!(adaptive.as_bytes().len() < plain.as_bytes().len());
+
+        for strategy in [FilterStrategy::MinSum, FilterStrategy::BruteForce] {
+            let options = EncodeOptions::new().filter(strategy);
+            let png = Png::from_img_with(img.clone(), &options).unwrap();
+            assert_eq!(png.to_img().unwrap().data(), img.data());
+        }
+    }
+
+    #[test]
+    fn test_interlaced_round_trip() {
+        let img = Png::try_from(&PNG_FILE[..]).unwrap().to_img().unwrap();
+        let options = EncodeOptions::new().interlace(true);
+        let png = Png::from_img_with(img.clone(), &options).unwrap();
+
+        let ihdr = Ihdr::try_from(png.chunk_by_type("IHDR").unwrap()).unwrap();
+        assert_eq!(ihdr.interlace, 1);
+        assert_eq!(png.to_img().unwrap().data(), img.data());
+
+        for (width, height) in [(1, 1), (3, 2), (9, 5), (17, 1)] {
+            let data = (0..width * height).map(|px| px * 0x01020304).collect();
+            let img = Img::from_vec(width, height, data);
+            let png = Png::from_img_with(img.clone(), &options).unwrap();
+            assert_eq!(png.to_img().unwrap().data(), img.data());
+        }
+    }
+
+    #[test]
+    fn test_interlaced_fixture() {
+        use crate::png::ColorType;
+
+        let ihdr = Ihdr {
+            bit_depth: 8,
+            color_type: ColorType::Grayscale,
+            interlace: 1,
+            ..Ihdr::new(2, 2)
+        };
+
+        // Pass 1 holds (0, 0), pass 6 holds (1, 0) and pass 7 holds row 1.
+        let scanlines = [0, 10, 0, 20, 1, 30, 10];
+        let png = Png::from_chunks(vec![
+            ihdr.to_chunk().unwrap(),
+            Chunk::idat(&fs::compress(&scanlines).unwrap()).unwrap(),
+            Chunk::iend().unwrap(),
+        ]);
+
+        assert_eq!(
+            png.to_img().unwrap().data(),
+            &vec![0x0A0A0AFF, 0x141414FF, 0x1E1E1EFF, 0x282828FF]
+        );
+    }
+
+    #[test]
+    fn test_from_img_reduces_color_type() {
+        use crate::{col, png::ColorType, Color};
+
+        let encode = |img: &Img, reduce| {
+            let options = EncodeOptions::new().reduce(reduce);
+            Png::from_img_with(img.clone(), &options).unwrap()
+        };
+
+        let color_type = |png: &Png| {
+            let ihdr = Ihdr::try_from(png.chunk_by_type("IHDR").unwrap()).unwrap();
+            (ihdr.color_type, ihdr.bit_depth)
+        };
+
+        let mut gray = Img::new_bg(64, 64, col!(0xFFFFFF));
+        gray.slice(..16, ..).fill(col!(0x555555));
+        gray.slice(..16, ..16).fill(col!(0xAAAAAA));
+        let mut indexed = gray.clone();
+        indexed.slice(40.., 40..).fill(col!(0xFF2000));
+        let mut translucent = indexed.clone();
+        translucent.slice(20..30, 20..30).fill(col!(0xFF200080));
+        let noise = (0..65536u32).map(|v| v.wrapping_mul(v * 7 + 31) >> 3 & 0xFF);
+        let opaque = Img::from_vec(256, 256, noise.map(|v| v << 16 | 0xFF).collect());
+        let dice = Png::try_from(&PNG_FILE[..]).unwrap().to_img().unwrap();
+
+        for (img, expected) in [
+            (&gray, (ColorType::Grayscale, 2)),
+            (&indexed, (ColorType::Indexed, 2)),
+            (&translucent, (ColorType::Indexed, 4)),
+            (&opaque, (ColorType::Indexed, 8)),
+            (&dice, (ColorType::Rgba, 8)),
+        ] {
+            let reduced = encode(img, true);
+            assert_eq!(color_type(&reduced), expected);
+            assert_eq!(reduced.clone().to_img().unwrap().data(), img.data());
+            assert!(reduced.as_bytes().len() <= encode(img, false).as_bytes().len());
+        }
+    }
+
+    #[test]
+    fn test_deep_round_trip() {
+        use crate::png::ColorType;
+
+        let to_deep = |png: Png| Img::<u64>::try_from(png).unwrap();
+        let color_type = |png: &Png| {
+            let ihdr = Ihdr::try_from(png.chunk_by_type("IHDR").unwrap()).unwrap();
+            (ihdr.color_type, ihdr.bit_depth)
+        };
+
+        let noise: Vec<u64> = (1..=99u64)
+            .map(|v| v.wrapping_mul(0x9E3779B97F4A7C15))
+            .collect();
+        let gray: Vec<u64> = (0..99u64)
+            .map(|v| ((v * 661) * 0x0001000100010000) | 0xFFFF)
+            .collect();
+        let narrow: Vec<u64> = (0..99u64)
+            .map(|v| (v * 0x0101010102020000) | 0xFFFF)
+            .collect();
+
+        for (data, expected) in [
+            (noise, (ColorType::Rgba, 16)),
+            (gray, (ColorType::Grayscale, 16)),
+            (narrow, (ColorType::Rgb, 8)),
+        ] {
+            let img = Img::from_vec(11, 9, data);
+
+            for interlace in [false, true] {
+                let options = EncodeOptions::new().interlace(interlace);
+                let png = Png::from_img_with(img.clone(), &options).unwrap();
+                assert_eq!(color_type(&png), expected);
+                assert_eq!(to_deep(png).data(), img.data());
+            }
+
+            let png = Png::from_img_with(img.clone(), &EncodeOptions::new().reduce(false)).unwrap();
+            assert_eq!(color_type(&png), (ColorType::Rgba, 16));
+            assert_eq!(to_deep(png).data(), img.data());
+        }
+    }
+
+    #[test]
+    fn test_deep_and_narrow_images_agree() {
+        let dice = Png::try_from(&PNG_FILE[..]).unwrap();
+        let narrow = dice.clone().to_img().unwrap();
+        let deep = Img::<u64>::try_from(dice).unwrap();
+
+        let widened: Vec<u64> = narrow
+            .data()
+            .iter()
+            .map(|px| u64::from_rgba16(px.to_rgba16()))
+            .collect();
+        assert_eq!(deep.data(), &widened);
+
+        let again = Png::from_img(deep).unwrap().to_img().unwrap();
+        assert_eq!(again.data(), narrow.data());
+    }
+
+    #[test]
+    fn test_color_space_is_preserved() {
+        use crate::png::{color_space::Gamma, ColorSpace, DecodeOptions, RenderingIntent};
+
+        let img = Png::try_from(&PNG_FILE[..]).unwrap().to_img().unwrap();
+        let expected = ColorSpace {
+            gamma: Some(Gamma::SRGB),
+            rendering_intent: Some(RenderingIntent::Perceptual),
+            ..ColorSpace::default()
+        };
+        assert_eq!(img.color_space(), &expected);
+
+        let png = Png::from_img(img.clone()).unwrap();
+        let names: Vec<String> = png.chunks()[..3]
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(names, ["IHDR", "gAMA", "sRGB"]);
+
+        let srgb: Img = Img::from_png_with(png, &DecodeOptions { srgb: true }).unwrap();
+        assert_eq!(srgb.data(), img.data());
+        assert_eq!(srgb.color_space(), &expected);
+    }
+
+    #[test]
+    fn test_to_img_converts_to_srgb() {
+        use crate::png::{color_space::Gamma, ColorSpace, DecodeOptions, RenderingIntent};
+
+        let mut img = Img::from_vec(2, 1, vec![0x808080FFu32, 0x00FF40FF]);
+        img.set_color_space(ColorSpace {
+            gamma: Some(Gamma(100000)),
+            ..ColorSpace::default()
+        });
+
+        let png = Png::from_img(img.clone()).unwrap();
+        assert_eq!(png.clone().to_img().unwrap().data(), img.data());
+
+        let srgb: Img = Img::from_png_with(png, &DecodeOptions { srgb: true }).unwrap();
+        assert_eq!(srgb.data(), &vec![0xBCBCBCFF, 0x00FF89FF]);
+        assert_eq!(
+            srgb.color_space(),
+            &ColorSpace::srgb(RenderingIntent::Perceptual)
+        );
+    }
+
     // This is the raw bytes for a shrunken version of the `dice.png` image on Wikipedia
     const PNG_FILE: [u8; 4803] = [
         137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 50, 0, 0, 0, 50, 8,
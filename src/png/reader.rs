@@ -0,0 +1,169 @@
+use super::{chunk::segment4, Chunk, ChunkType, Png};
+use crate::{convert, err::*, INT_MAX};
+use std::io::{ErrorKind, Read};
+
+/// Reads chunks one at a time from any `Read`, holding no more than the
+/// current chunk in memory. Iteration ends at the end of the input or
+/// after the first error. Chunks following IEND are still read, since
+/// older versions of `encode` placed messages there.
+#[derive(Debug)]
+pub struct ChunkReader<R> {
+    inner: R,
+    done: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    /// Largest buffer reserved up front, so a corrupt length cannot cause
+    /// a huge allocation before any data has arrived.
+    const RESERVE: u32 = 65536;
+
+    /// Read bare chunks, with no PNG signature expected.
+    pub fn new(inner: R) -> Self {
+        Self { inner, done: false }
+    }
+
+    /// Read a PNG stream, checking the signature before any chunks.
+    pub fn png(mut inner: R) -> PngRes<Self> {
+        let mut signature = [0; 8];
+        let len = fill(&mut inner, &mut signature)?;
+        PngErr::is_or(
+            len == 8 && signature == Png::STANDARD_HEADER,
+            PngErr::InvalidHeader,
+        )?;
+
+        Ok(Self::new(inner))
+    }
+
+    fn read_chunk(&mut self) -> PngRes<Option<Chunk>> {
+        let mut head = [0; 8];
+        match fill(&mut self.inner, &mut head)? {
+            0 => return Ok(None),
+            8 => (),
+            _ => return Err(PngErr::ShortChunk),
+        }
+
+        let length = u32::from_be_bytes(segment4(&head[0..4])?);
+        PngErr::not_or(length > INT_MAX, PngErr::LengthMismatch)?;
+        let chunk_type = ChunkType::from_bytes(&head[4..8])?;
+
+        let mut data = Vec::with_capacity(convert!(usize; length.min(Self::RESERVE))?);
+        (&mut self.inner)
+            .take(u64::from(length))
+            .read_to_end(&mut data)
+            .map_err(|_| PngErr::ReadError)?;
+        PngErr::is_or(data.len() == convert!(usize; length)?, PngErr::ShortChunk)?;
+
+        let mut crc = [0; 4];
+        PngErr::is_or(fill(&mut self.inner, &mut crc)? == 4, PngErr::ShortChunk)?;
+
+        Chunk::from_parts(chunk_type, data, u32::from_be_bytes(crc)).map(Some)
+    }
+}
+
+impl<R: Read> Iterator for ChunkReader<R> {
+    type Item = PngRes<Chunk>;
+    fn next(&mut self) -> Option<PngRes<Chunk>> {
+        if self.done {
+            return None;
+        }
+
+        let chunk = self.read_chunk().transpose();
+        self.done = !matches!(chunk, Some(Ok(_)));
+        chunk
+    }
+}
+
+/// Read until `buf` is full or the input ends, returning the bytes read.
+fn fill(reader: &mut impl Read, buf: &mut [u8]) -> PngRes<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return Err(PngErr::ReadError),
+        }
+    }
+
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::{AsChunkRef, Ihdr};
+    use std::{io, str::FromStr};
+
+    /// Hands out at most three bytes per read, like a slow pipe.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.0.len()).min(3);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    fn png() -> Png {
+        Png::from_chunks(vec![
+            Ihdr::new(1, 1).to_chunk().unwrap(),
+            Chunk::new(ChunkType::from_str("teSt").unwrap(), b"streamed".to_vec()),
+            Chunk::idat(&[1, 2, 3]).unwrap(),
+            Chunk::iend().unwrap(),
+        ])
+    }
+
+    #[test]
+    fn test_reads_chunks_in_pieces() {
+        let bytes = png().as_bytes();
+        let chunks: Vec<Chunk> = ChunkReader::png(Trickle(&bytes))
+            .unwrap()
+            .collect::<PngRes<_>>()
+            .unwrap();
+
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(chunks[1].data_as_string().unwrap(), "streamed");
+        assert_eq!(Png::from_chunks(chunks).as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_reads_past_iend() {
+        let mut chunks = png().chunks().to_vec();
+        chunks.push(Chunk::new(ChunkType::from_str("ruSt").unwrap(), Vec::new()));
+
+        let bytes = Png::from_chunks(chunks).as_bytes();
+        let chunks: Vec<PngRes<Chunk>> = ChunkReader::png(&bytes[..]).unwrap().collect();
+        assert_eq!(chunks.len(), 5);
+        assert_eq!(chunks[4].as_ref().unwrap().chunk_type().to_string(), "ruSt");
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        assert!(ChunkReader::png(&b"\x89PNG"[..]).is_err());
+        assert!(ChunkReader::png(&[0u8; 8][..]).is_err());
+
+        let bytes = png().as_bytes();
+        let mut reader = ChunkReader::png(&bytes[..40]).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+
+        let mut corrupt = bytes.clone();
+        corrupt[45] ^= 1;
+        let mut reader = ChunkReader::png(&corrupt[..]).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(reader.next(), Some(Err(PngErr::CRCMismatch))));
+    }
+
+    #[test]
+    fn test_huge_length_is_not_reserved() {
+        let mut bytes = 0x7FFF_FFFFu32.to_be_bytes().to_vec();
+        bytes.extend_from_slice(b"IDAT");
+        bytes.extend_from_slice(&[0; 100]);
+
+        let mut reader = ChunkReader::new(&bytes[..]);
+        assert!(matches!(reader.next(), Some(Err(PngErr::ShortChunk))));
+    }
+}
@@ -0,0 +1,407 @@
+use super::{Chunk, ChunkType, Png, Problem, Violation};
+use crate::{err::*, fs, INT_MAX};
+use std::fmt;
+
+/// The chunks which could be read from a damaged file, and what was wrong
+/// with it. Problems about a kept chunk give its index in `png`; those
+/// about skipped bytes have no index.
+#[derive(Debug, Clone)]
+pub struct Recovered {
+    pub png: Png,
+    pub problems: Vec<Violation>,
+}
+
+/// One change made by [`Png::repair`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Repair {
+    Signature,
+    Crc(String),
+    /// The offset and length of bytes which couldn't be read as a chunk.
+    Dropped(usize, usize),
+    InvalidType(usize),
+    Truncated(String),
+    MovedIhdr,
+    MovedIend,
+    AddedIend,
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Repair::Signature => write!(f, "rewrote the PNG signature"),
+            Repair::Crc(name) => write!(f, "recomputed the CRC of the {} chunk", name),
+            Repair::Dropped(offset, length) => {
+                write!(f, "dropped {} unreadable bytes at byte {}", length, offset)
+            }
+            Repair::InvalidType(offset) => {
+                write!(
+                    f,
+                    "dropped the chunk with an invalid type at byte {}",
+                    offset
+                )
+            }
+            Repair::Truncated(name) => {
+                write!(f, "kept what remains of the truncated {} chunk", name)
+            }
+            Repair::MovedIhdr => write!(f, "moved IHDR to the start"),
+            Repair::MovedIend => write!(f, "moved IEND to the end"),
+            Repair::AddedIend => write!(f, "added the missing IEND"),
+        }
+    }
+}
+
+impl Png {
+    /// Read as many chunks as possible from `bytes`. A chunk with a bad
+    /// CRC is kept if the next chunk starts where its length says, as is
+    /// the readable part of a truncated chunk. Anything else that doesn't
+    /// parse is skipped up to the next header whose CRC checks out.
+    pub fn recover(bytes: &[u8]) -> Recovered {
+        let mut chunks = Vec::new();
+        let mut problems = Vec::new();
+        let mut report = |index: Option<usize>, offset: usize, problem: Problem| {
+            problems.push(Violation {
+                index,
+                offset,
+                problem,
+            })
+        };
+
+        let mut offset = if bytes.get(..8) == Some(&Self::STANDARD_HEADER[..]) {
+            8
+        } else {
+            report(None, 0, Problem::BadSignature);
+            let start = resync(bytes, 0).unwrap_or(bytes.len());
+            if start > 0 {
+                report(None, 0, Problem::Skipped(start));
+            }
+            start
+        };
+
+        while offset < bytes.len() {
+            let index = Some(chunks.len());
+            let Some((length, type_bytes)) = header(bytes, offset) else {
+                report(None, offset, Problem::Truncated);
+                break;
+            };
+
+            if length >= INT_MAX {
+                report(None, offset, Problem::BadLength);
+                let next = resync(bytes, offset + 1).unwrap_or(bytes.len());
+                report(None, offset, Problem::Skipped(next - offset));
+                offset = next;
+                continue;
+            }
+
+            let length = length as usize;
+            let end = offset + 12 + length;
+            if !intact_at(bytes, offset) && !plausible_at(bytes, end) {
+                if let Some(next) = resync(bytes, offset + 1) {
+                    report(None, offset, Problem::Skipped(next - offset));
+                    offset = next;
+                    continue;
+                }
+
+                match ChunkType::from_bytes(type_bytes) {
+                    Ok(chunk_type) if end > bytes.len() => {
+                        let data = bytes[offset + 8..bytes.len().min(end - 4)].to_vec();
+                        chunks.push(Chunk::new(chunk_type, data));
+                        report(index, offset, Problem::Truncated);
+                    }
+                    _ => report(None, offset, Problem::Skipped(bytes.len() - offset)),
+                }
+
+                break;
+            }
+
+            match ChunkType::from_bytes(type_bytes) {
+                Ok(chunk_type) => {
+                    let chunk = Chunk::new(chunk_type, bytes[offset + 8..end - 4].to_vec());
+                    if chunk.crc().to_be_bytes() != bytes[end - 4..end] {
+                        report(index, offset, Problem::BadCrc);
+                    }
+
+                    chunks.push(chunk);
+                }
+                Err(_) => report(None, offset, Problem::BadChunkType),
+            }
+
+            offset = end;
+        }
+
+        Recovered {
+            png: Png::from_chunks(chunks),
+            problems,
+        }
+    }
+
+    pub fn load_lenient(filename: &str) -> PngRes<Recovered> {
+        Ok(Self::recover(&fs::read(filename)?))
+    }
+
+    /// Recover what can be read from `bytes`, then put IHDR first and a
+    /// single IEND last, returning the file along with what was changed.
+    /// Writing the chunks back out recomputes every CRC. Fails with
+    /// `InvalidIHDR` if no IHDR could be recovered, since the result
+    /// wouldn't be an image.
+    pub fn repair(bytes: &[u8]) -> PngRes<(Png, Vec<Repair>)> {
+        let Recovered { png, problems } = Self::recover(bytes);
+        let mut chunks = png.chunks().to_vec();
+        let name = |index: Option<usize>| {
+            index
+                .and_then(|index| chunks.get(index))
+                .map(|chunk| chunk.chunk_type().to_string())
+        };
+
+        let mut repairs: Vec<_> = problems
+            .iter()
+            .filter_map(|violation| match violation.problem {
+                Problem::BadSignature => Some(Repair::Signature),
+                Problem::BadCrc => name(violation.index).map(Repair::Crc),
+                Problem::BadChunkType => Some(Repair::InvalidType(violation.offset)),
+                Problem::Skipped(length) => Some(Repair::Dropped(violation.offset, length)),
+                Problem::Truncated => Some(name(violation.index).map_or(
+                    Repair::Dropped(violation.offset, bytes.len() - violation.offset),
+                    Repair::Truncated,
+                )),
+                _ => None,
+            })
+            .collect();
+
+        let is = |chunk: &Chunk, name: &str| chunk.chunk_type().to_string() == name;
+        let index = chunks
+            .iter()
+            .position(|chunk| is(chunk, "IHDR"))
+            .ok_or(PngErr::InvalidIHDR)?;
+        if index > 0 {
+            let ihdr = chunks.remove(index);
+            chunks.insert(0, ihdr);
+            repairs.push(Repair::MovedIhdr);
+        }
+
+        let last_is_iend = chunks.last().is_some_and(|chunk| is(chunk, "IEND"));
+        let before = chunks.len();
+        chunks.retain(|chunk| !is(chunk, "IEND"));
+        match before - chunks.len() {
+            0 => repairs.push(Repair::AddedIend),
+            1 if last_is_iend => (),
+            _ => repairs.push(Repair::MovedIend),
+        }
+
+        chunks.push(Chunk::iend()?);
+        Ok((Png::from_chunks(chunks), repairs))
+    }
+}
+
+/// The length and type bytes of the chunk at `offset`, if there is room
+/// for a whole header and CRC. The length may be out of range.
+fn header(bytes: &[u8], offset: usize) -> Option<(u32, &[u8])> {
+    let head = bytes.get(offset..offset.checked_add(12)?)?;
+    let length = u32::from_be_bytes([head[0], head[1], head[2], head[3]]);
+    Some((length, &head[4..8]))
+}
+
+/// Whether a chunk could start at `offset`, judging only by its type. The
+/// end of the file counts, since the last chunk ends there.
+fn plausible_at(bytes: &[u8], offset: usize) -> bool {
+    offset == bytes.len()
+        || header(bytes, offset).is_some_and(|(_, type_bytes)| {
+            ChunkType::from_bytes(type_bytes).is_ok_and(|t| t.is_reserved_bit_valid())
+        })
+}
+
+/// Whether the chunk at `offset` has a valid length, is complete and its
+/// CRC is correct.
+fn intact_at(bytes: &[u8], offset: usize) -> bool {
+    header(bytes, offset).is_some_and(|(length, _)| {
+        let end = offset + 12 + length as usize;
+        length < INT_MAX
+            && end <= bytes.len()
+            && crc::crc32::checksum_ieee(&bytes[offset + 4..end - 4]).to_be_bytes()
+                == bytes[end - 4..end]
+    })
+}
+
+/// Find the next offset from `start` holding a plausible, intact chunk.
+fn resync(bytes: &[u8], start: usize) -> Option<usize> {
+    (start..bytes.len()).find(|&offset| plausible_at(bytes, offset) && intact_at(bytes, offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        img::Img,
+        png::{EncodeOptions, Message},
+    };
+
+    fn png() -> Png {
+        let img: Img = Img::new_bg(6, 6, 0x336699FF);
+        let mut png = Png::from_img_with(img, &EncodeOptions::new()).unwrap();
+        png.encode("ruSt", "a message").unwrap();
+        png
+    }
+
+    fn names(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect()
+    }
+
+    fn problems(recovered: &Recovered) -> Vec<Problem> {
+        recovered
+            .problems
+            .iter()
+            .map(|violation| violation.problem.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_file() {
+        let recovered = Png::recover(&png().as_bytes());
+        assert!(recovered.problems.is_empty());
+        assert_eq!(recovered.png.as_bytes(), png().as_bytes());
+    }
+
+    #[test]
+    fn test_bad_crc_is_kept() {
+        let mut bytes = png().as_bytes();
+        bytes[8 + 8] ^= 0x40;
+
+        assert!(Png::try_from(&bytes[..]).is_err());
+        let recovered = Png::recover(&bytes);
+        assert_eq!(problems(&recovered), [Problem::BadCrc]);
+        assert_eq!(recovered.problems[0].index, Some(0));
+        assert_eq!(names(&recovered.png), names(&png()));
+        assert_eq!(
+            recovered.png.decode_nth("ruSt", 0).unwrap(),
+            Message::from("a message")
+        );
+    }
+
+    #[test]
+    fn test_bad_type_is_skipped() {
+        let mut bytes = png().as_bytes();
+        let idat = bytes.windows(4).position(|w| w == b"IDAT").unwrap();
+        bytes[idat] = b'#';
+
+        let recovered = Png::recover(&bytes);
+        assert_eq!(problems(&recovered), [Problem::BadChunkType]);
+        assert_eq!(names(&recovered.png), ["IHDR", "PLTE", "ruSt", "IEND"]);
+    }
+
+    #[test]
+    fn test_resync_after_bad_length() {
+        let mut bytes = png().as_bytes();
+        bytes[8] = 0x10;
+
+        let recovered = Png::recover(&bytes);
+        assert_eq!(problems(&recovered), [Problem::Skipped(25)]);
+        assert_eq!(recovered.problems[0].offset, 8);
+        assert_eq!(names(&recovered.png), ["PLTE", "IDAT", "ruSt", "IEND"]);
+
+        let mut garbage = b"junk".to_vec();
+        garbage.extend(png().as_bytes());
+        let recovered = Png::recover(&garbage);
+        assert_eq!(
+            problems(&recovered),
+            [Problem::BadSignature, Problem::Skipped(12)]
+        );
+        assert_eq!(names(&recovered.png), names(&png()));
+    }
+
+    #[test]
+    fn test_length_out_of_range() {
+        let mut bytes = png().as_bytes();
+        bytes[8] = 0x80;
+
+        let recovered = Png::recover(&bytes);
+        assert_eq!(
+            problems(&recovered),
+            [Problem::BadLength, Problem::Skipped(25)]
+        );
+        assert_eq!(names(&recovered.png), ["PLTE", "IDAT", "ruSt", "IEND"]);
+
+        let mut bytes = png().as_bytes();
+        let idat = bytes.windows(4).position(|w| w == b"IDAT").unwrap();
+        bytes[idat - 4] |= 0x80;
+
+        let recovered = Png::recover(&bytes);
+        assert_eq!(problems(&recovered)[0], Problem::BadLength);
+        assert!(matches!(problems(&recovered)[1], Problem::Skipped(_)));
+        assert_eq!(names(&recovered.png), ["IHDR", "PLTE", "ruSt", "IEND"]);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let bytes = png().as_bytes();
+        let recovered = Png::recover(&bytes[..bytes.len() - 18]);
+
+        assert_eq!(problems(&recovered), [Problem::Truncated]);
+        assert_eq!(recovered.problems[0].index, Some(3));
+        let message = recovered.png.chunk_by_type("ruSt").unwrap();
+        assert_eq!(message.data(), b"\xF8a messa");
+
+        let iend = bytes.len() - 12;
+        let recovered = Png::recover(&bytes[..iend + 6]);
+        assert_eq!(problems(&recovered), [Problem::Truncated]);
+        assert_eq!(recovered.problems[0].index, None);
+        assert_eq!(recovered.problems[0].offset, iend);
+        assert_eq!(names(&recovered.png), ["IHDR", "PLTE", "IDAT", "ruSt"]);
+
+        let (_, repairs) = Png::repair(&bytes[..iend + 6]).unwrap();
+        assert_eq!(repairs, [Repair::Dropped(iend, 6), Repair::AddedIend]);
+    }
+
+    #[test]
+    fn test_repair() {
+        let mut chunks = png().chunks().to_vec();
+        chunks.swap(3, 4);
+        let mut bytes = Png::from_chunks(chunks).as_bytes();
+        bytes[8 + 8 + 13 + 3] ^= 1;
+        let end = bytes.len();
+        bytes.extend(b"garbage after");
+
+        let (repaired, repairs) = Png::repair(&bytes).unwrap();
+        assert_eq!(
+            repairs,
+            [
+                Repair::Crc("IHDR".to_string()),
+                Repair::Dropped(end, 13),
+                Repair::MovedIend
+            ]
+        );
+        assert_eq!(names(&repaired), ["IHDR", "PLTE", "IDAT", "ruSt", "IEND"]);
+        assert!(Png::try_from(&repaired.as_bytes()[..]).is_ok());
+        assert!(repaired.to_img().is_ok());
+    }
+
+    #[test]
+    fn test_repair_order() {
+        let mut chunks = png().chunks().to_vec();
+        chunks.truncate(3);
+        chunks.swap(0, 1);
+        let bytes = Png::from_chunks(chunks).as_bytes();
+
+        let (repaired, repairs) = Png::repair(&bytes).unwrap();
+        assert_eq!(repairs, [Repair::MovedIhdr, Repair::AddedIend]);
+        assert_eq!(names(&repaired), ["IHDR", "PLTE", "IDAT", "IEND"]);
+
+        let (_, repairs) = Png::repair(&repaired.as_bytes()).unwrap();
+        assert!(repairs.is_empty());
+    }
+
+    #[test]
+    fn test_repair_needs_ihdr() {
+        let mut bytes = png().as_bytes();
+        bytes[8] = 0x80;
+        assert_eq!(Png::repair(&bytes).unwrap_err(), PngErr::InvalidIHDR);
+
+        let mut chunks = png().chunks().to_vec();
+        chunks.remove(0);
+        assert_eq!(
+            Png::repair(&Png::from_chunks(chunks).as_bytes()).unwrap_err(),
+            PngErr::InvalidIHDR
+        );
+    }
+}
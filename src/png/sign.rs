@@ -0,0 +1,402 @@
+use super::{
+    crypt::{from_hex, to_hex},
+    AsChunkRef, Chunk, ChunkType, Png,
+};
+use crate::err::*;
+use ed25519_dalek::{Signature, Signer as _, SigningKey, Verifier as _, VerifyingKey};
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+use std::{fmt, str::FromStr};
+
+/// The chunk holding a signature. It is unsafe to copy, so editors that
+/// don't know it drop it along with the image they no longer vouch for.
+pub const SIGNATURE_TYPE: &str = "siGN";
+
+/// Set in the flags when the signature covers the pixels too.
+const PIXELS: u8 = 1;
+
+/// The chunks covered by a signature over the pixels.
+const PIXEL_TYPES: [&str; 11] = [
+    "IHDR", "PLTE", "tRNS", "gAMA", "cHRM", "sRGB", "iCCP", "IDAT", "acTL", "fcTL", "fdAT",
+];
+
+/// Prefixed to what is signed, so a signature can't be passed off as one
+/// made for anything else.
+const DOMAIN: &[u8] = b"pngme siGN\0";
+
+const DIGEST: usize = 32;
+const KEY: usize = 32;
+const SIGNATURE: usize = 64;
+
+/// An Ed25519 private key, which signs for its `Verifier`. Written and
+/// parsed as hex.
+pub struct Signer(SigningKey);
+
+impl Signer {
+    pub fn generate() -> Self {
+        Self(SigningKey::generate(&mut OsRng))
+    }
+
+    pub fn verifier(&self) -> Verifier {
+        Verifier(self.0.verifying_key())
+    }
+}
+
+impl FromStr for Signer {
+    type Err = PngErr;
+    fn from_str(key: &str) -> PngRes<Self> {
+        Ok(Self(SigningKey::from_bytes(&from_hex(key)?)))
+    }
+}
+
+impl fmt::Display for Signer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", to_hex(self.0.as_bytes()))
+    }
+}
+
+/// An Ed25519 public key, saying who made a signature. Written and parsed
+/// as hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Verifier(VerifyingKey);
+
+impl FromStr for Verifier {
+    type Err = PngErr;
+    fn from_str(key: &str) -> PngRes<Self> {
+        VerifyingKey::from_bytes(&from_hex(key)?)
+            .map(Self)
+            .map_err(|_| PngErr::InvalidKey)
+    }
+}
+
+impl fmt::Display for Verifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", to_hex(self.0.as_bytes()))
+    }
+}
+
+/// What one signature chunk covers, who made it and whether it still
+/// holds for the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    pub signer: Verifier,
+    pub chunk_type: String,
+    /// Whether the image header, palette and data are covered as well.
+    pub pixels: bool,
+    /// Whether the signature was really made by `signer`.
+    pub genuine: bool,
+    pub message_intact: bool,
+    /// Always true when the pixels aren't covered.
+    pub pixels_intact: bool,
+}
+
+impl Verification {
+    pub fn is_valid(&self) -> bool {
+        self.genuine && self.message_intact && self.pixels_intact
+    }
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let covers = if self.pixels { " and pixels" } else { "" };
+        write!(
+            f,
+            "{} signed by {}, covering the message{}: ",
+            self.chunk_type, self.signer, covers
+        )?;
+
+        match (self.genuine, self.message_intact, self.pixels_intact) {
+            (false, _, _) => write!(f, "the signature is forged or corrupt"),
+            (true, true, true) => write!(f, "unchanged"),
+            (true, false, true) => write!(f, "the message has changed"),
+            (true, true, false) => write!(f, "the pixels have changed"),
+            (true, false, false) => write!(f, "the message and pixels have changed"),
+        }
+    }
+}
+
+/// The fields of a signature chunk: flags, the signed chunk type, the
+/// signer's key, the message digest, the pixel digest if the flags say
+/// so, then the signature of all of them.
+struct Fields<'a> {
+    signed: &'a [u8],
+    chunk_type: String,
+    signer: Verifier,
+    message: &'a [u8],
+    pixels: Option<&'a [u8]>,
+    signature: Signature,
+}
+
+impl<'a> Fields<'a> {
+    fn parse(data: &'a [u8]) -> PngRes<Self> {
+        let pixels = data.first().is_some_and(|flags| flags & PIXELS != 0);
+        let digests = if pixels { 2 * DIGEST } else { DIGEST };
+        PngErr::is_or(
+            data.len() == 1 + 4 + KEY + digests + SIGNATURE,
+            PngErr::InvalidSignature,
+        )?;
+
+        let (signed, signature) = data.split_at(data.len() - SIGNATURE);
+        let chunk_type = ChunkType::try_from([signed[1], signed[2], signed[3], signed[4]])
+            .map_err(|_| PngErr::InvalidSignature)?;
+        let mut key = [0; KEY];
+        key.copy_from_slice(&signed[5..5 + KEY]);
+        let signer = VerifyingKey::from_bytes(&key).map_err(|_| PngErr::InvalidSignature)?;
+        let message = &signed[5 + KEY..5 + KEY + DIGEST];
+
+        Ok(Self {
+            signed,
+            chunk_type: chunk_type.to_string(),
+            signer: Verifier(signer),
+            message,
+            pixels: pixels.then(|| &signed[5 + KEY + DIGEST..]),
+            signature: Signature::from_slice(signature).map_err(|_| PngErr::InvalidSignature)?,
+        })
+    }
+}
+
+impl Png {
+    /// Sign every chunk of `chunk_type` with `signer`, and the chunks that
+    /// make up the image too if `pixels`, adding a signature chunk before
+    /// IEND. One made before by the same signer for the same type is
+    /// removed.
+    pub fn sign(&mut self, chunk_type: &str, signer: &Signer, pixels: bool) -> PngRes<&mut Self> {
+        PngErr::not_or(chunk_type == SIGNATURE_TYPE, PngErr::InvalidOption)?;
+        let parsed = ChunkType::from_str(chunk_type)?;
+        PngErr::not_or(
+            self.chunks_by_type(chunk_type).is_empty(),
+            PngErr::ChunkNotFound,
+        )?;
+
+        let verifier = signer.verifier();
+        let mut signed = vec![if pixels { PIXELS } else { 0 }];
+        signed.extend(parsed.bytes());
+        signed.extend(verifier.0.as_bytes());
+        signed.extend(message_digest(self, chunk_type));
+        if pixels {
+            signed.extend(pixel_digest(self));
+        }
+        let signature = signer.0.sign(&[DOMAIN, &signed].concat());
+        signed.extend(signature.to_bytes());
+
+        let previous = self
+            .chunks_by_type(SIGNATURE_TYPE)
+            .iter()
+            .position(|chunk| {
+                Fields::parse(chunk.data()).is_ok_and(|fields| {
+                    fields.signer == verifier && fields.chunk_type == chunk_type
+                })
+            });
+        if let Some(index) = previous {
+            self.remove_nth_chunk(SIGNATURE_TYPE, index)?;
+        }
+
+        self.append_chunk(Chunk::new(ChunkType::from_str(SIGNATURE_TYPE)?, signed))
+    }
+}
+
+impl<C: AsChunkRef> Png<C> {
+    /// Check every signature chunk against the file as it is now.
+    pub fn verify(&self) -> PngRes<Vec<Verification>> {
+        self.chunks_by_type(SIGNATURE_TYPE)
+            .into_iter()
+            .map(|chunk| {
+                let fields = Fields::parse(chunk.as_chunk_ref().data())?;
+                let genuine = fields
+                    .signer
+                    .0
+                    .verify(&[DOMAIN, fields.signed].concat(), &fields.signature)
+                    .is_ok();
+
+                Ok(Verification {
+                    message_intact: message_digest(self, &fields.chunk_type) == fields.message,
+                    pixels_intact: fields
+                        .pixels
+                        .is_none_or(|digest| pixel_digest(self) == digest),
+                    pixels: fields.pixels.is_some(),
+                    signer: fields.signer,
+                    chunk_type: fields.chunk_type,
+                    genuine,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The chunks of `chunk_type` in order, each with its length so that
+/// moving bytes between them changes the digest.
+fn message_digest<C: AsChunkRef>(png: &Png<C>, chunk_type: &str) -> [u8; DIGEST] {
+    let mut hasher = Sha256::new();
+    for chunk in png.chunks_by_type(chunk_type) {
+        let chunk = chunk.as_chunk_ref();
+        hasher.update(chunk.length().to_be_bytes());
+        hasher.update(chunk.data());
+    }
+    hasher.finalize().into()
+}
+
+/// The chunks that decide what the image looks like, in file order: the
+/// header, palette, transparency, colour space, image data and animation.
+/// Each is prefixed with its type and length, so moving bytes between them
+/// or relabelling one changes the digest.
+fn pixel_digest<C: AsChunkRef>(png: &Png<C>) -> [u8; DIGEST] {
+    let mut hasher = Sha256::new();
+    for chunk in png.chunks() {
+        let chunk = chunk.as_chunk_ref();
+        if PIXEL_TYPES.contains(&chunk.chunk_type().to_string().as_str()) {
+            hasher.update(chunk.chunk_type().bytes());
+            hasher.update(chunk.length().to_be_bytes());
+            hasher.update(chunk.data());
+        }
+    }
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::Placement;
+
+    fn png() -> Png {
+        let chunk =
+            |name: &str, data: &[u8]| Chunk::new(ChunkType::from_str(name).unwrap(), data.to_vec());
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("IDAT", b"pixels"),
+            chunk("IEND", b""),
+        ]);
+        png.encode("ruSt", "signed message").unwrap();
+        png
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let (alice, bob) = (Signer::generate(), Signer::generate());
+        let mut png = png();
+        png.sign("ruSt", &alice, false)
+            .unwrap()
+            .sign("ruSt", &bob, true)
+            .unwrap()
+            .sign("ruSt", &alice, false)
+            .unwrap();
+
+        let verified = png.verify().unwrap();
+        assert_eq!(verified.len(), 2);
+        assert!(verified.iter().all(Verification::is_valid));
+        assert_eq!(verified[0].signer, bob.verifier());
+        assert!(verified[0].pixels);
+        assert_eq!(verified[1].signer, alice.verifier());
+        assert_eq!(
+            png.chunks().last().unwrap().chunk_type().to_string(),
+            "IEND"
+        );
+
+        let png: Png = Png::try_from(png.as_bytes().as_slice()).unwrap();
+        assert!(Png::borrowed(&png.as_bytes()).unwrap().verify().unwrap()[0].is_valid());
+
+        assert!(png.clone().sign("noNe", &alice, false).is_err());
+        assert!(png.clone().sign(SIGNATURE_TYPE, &alice, false).is_err());
+    }
+
+    #[test]
+    fn test_changes_are_caught() {
+        let signer = Signer::generate();
+        let mut png = png();
+        png.sign("ruSt", &signer, true).unwrap();
+
+        let mut edited = png.clone();
+        edited.encode("ruSt", "edited message").unwrap();
+        let verified = &edited.verify().unwrap()[0];
+        assert!(verified.genuine && !verified.message_intact && verified.pixels_intact);
+
+        let mut repainted = png.clone();
+        repainted.remove_chunk("IDAT").unwrap();
+        repainted
+            .append_chunk(Chunk::new(
+                ChunkType::from_str("IDAT").unwrap(),
+                b"other".to_vec(),
+            ))
+            .unwrap();
+        let verified = &repainted.verify().unwrap()[0];
+        assert!(verified.message_intact && !verified.pixels_intact);
+
+        let palette = |rgb: Vec<u8>| Chunk::new(ChunkType::from_str("PLTE").unwrap(), rgb);
+        let mut recoloured = png.clone();
+        recoloured
+            .insert_chunk(palette(vec![255, 0, 0]), Placement::AfterIhdr)
+            .unwrap();
+        assert!(!recoloured.verify().unwrap()[0].pixels_intact);
+        recoloured.sign("ruSt", &signer, true).unwrap();
+        assert!(recoloured.verify().unwrap()[0].is_valid());
+        recoloured.remove_chunk("PLTE").unwrap();
+        recoloured
+            .insert_chunk(palette(vec![0, 255, 0]), Placement::AfterIhdr)
+            .unwrap();
+        let verified = &recoloured.verify().unwrap()[0];
+        assert!(verified.message_intact && !verified.pixels_intact);
+
+        let mut forged = png;
+        let mut signature = forged.remove_chunk(SIGNATURE_TYPE).unwrap().data().to_vec();
+        *signature.last_mut().unwrap() ^= 1;
+        forged
+            .append_chunk(Chunk::new(
+                ChunkType::from_str(SIGNATURE_TYPE).unwrap(),
+                signature,
+            ))
+            .unwrap();
+        let verified = &forged.verify().unwrap()[0];
+        assert!(!verified.genuine && !verified.is_valid());
+
+        forged
+            .append_chunk(Chunk::new(
+                ChunkType::from_str(SIGNATURE_TYPE).unwrap(),
+                vec![0; 3],
+            ))
+            .unwrap();
+        assert_eq!(forged.verify(), Err(PngErr::InvalidSignature));
+    }
+
+    #[test]
+    fn test_animation_and_colour_are_caught() {
+        let chunk =
+            |name: &str, data: &[u8]| Chunk::new(ChunkType::from_str(name).unwrap(), data.to_vec());
+        let signer = Signer::generate();
+        let mut png = png();
+        png.insert_chunk(
+            chunk("acTL", &[0, 0, 0, 1, 0, 0, 0, 0]),
+            Placement::BeforeIdat,
+        )
+        .unwrap();
+        png.sign("ruSt", &signer, true).unwrap();
+        assert!(png.verify().unwrap()[0].is_valid());
+
+        let mut looped = png.clone();
+        looped.remove_chunk("acTL").unwrap();
+        looped
+            .insert_chunk(
+                chunk("acTL", &[0, 0, 0, 1, 0, 0, 0, 3]),
+                Placement::BeforeIdat,
+            )
+            .unwrap();
+        let verified = &looped.verify().unwrap()[0];
+        assert!(verified.message_intact && !verified.pixels_intact);
+
+        let mut still = png.clone();
+        still.remove_chunk("acTL").unwrap();
+        assert!(!still.verify().unwrap()[0].pixels_intact);
+
+        let mut darkened = png;
+        darkened
+            .insert_chunk(chunk("gAMA", &[0, 0, 0xB1, 0x8F]), Placement::BeforeIdat)
+            .unwrap();
+        assert!(!darkened.verify().unwrap()[0].pixels_intact);
+    }
+
+    #[test]
+    fn test_keys() {
+        let signer = Signer::generate();
+        let parsed: Signer = signer.to_string().parse().unwrap();
+        assert_eq!(parsed.verifier(), signer.verifier());
+        assert_eq!(signer.verifier().to_string().parse(), Ok(signer.verifier()));
+    }
+}
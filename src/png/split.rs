@@ -0,0 +1,169 @@
+use super::message::PART;
+use crate::{convert, err::*};
+
+/// The part marker, then the part's sequence number, the total number of
+/// parts and the CRC of the whole message, each as a big endian u32.
+pub const HEADER: usize = 13;
+
+/// One part of a split message, borrowed from its chunk's data.
+#[derive(Debug)]
+struct Part<'a> {
+    sequence: u32,
+    total: u32,
+    crc: u32,
+    payload: &'a [u8],
+}
+
+impl<'a> Part<'a> {
+    fn parse(data: &'a [u8]) -> Option<Self> {
+        let field =
+            |at: usize| u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+
+        is_part(data).then(|| Self {
+            sequence: field(1),
+            total: field(5),
+            crc: field(9),
+            payload: &data[HEADER..],
+        })
+    }
+}
+
+pub fn is_part(data: &[u8]) -> bool {
+    data.len() >= HEADER && data[0] == PART
+}
+
+/// Split `message` into parts of at most `size` bytes, headers included.
+/// There is always at least one part, even for an empty message, and a
+/// `size` with no room after the header is `SplitTooSmall`.
+pub fn split(message: &[u8], size: usize) -> PngRes<Vec<Vec<u8>>> {
+    PngErr::is_or(size > HEADER, PngErr::SplitTooSmall)?;
+
+    let payloads: Vec<&[u8]> = if message.is_empty() {
+        vec![message]
+    } else {
+        message.chunks(size - HEADER).collect()
+    };
+
+    let total = convert!(u32; payloads.len())?;
+    let crc = crc::crc32::checksum_ieee(message);
+
+    payloads
+        .into_iter()
+        .enumerate()
+        .map(|(sequence, payload)| {
+            let sequence = convert!(u32; sequence)?;
+            Ok([
+                &[PART][..],
+                &sequence.to_be_bytes(),
+                &total.to_be_bytes(),
+                &crc.to_be_bytes(),
+                payload,
+            ]
+            .concat())
+        })
+        .collect()
+}
+
+/// Put the parts of a message back together in sequence, whatever order
+/// they're given in, checking none are missing and the result's CRC.
+pub fn join<'a>(parts: impl IntoIterator<Item = &'a [u8]>) -> PngRes<Vec<u8>> {
+    let mut parts = parts
+        .into_iter()
+        .map(|data| Part::parse(data).ok_or(PngErr::InvalidPart))
+        .collect::<PngRes<Vec<_>>>()?;
+    parts.sort_by_key(|part| part.sequence);
+
+    let first = parts.first().ok_or(PngErr::MissingPart)?;
+    let (total, crc) = (first.total, first.crc);
+    PngErr::is_or(
+        parts
+            .iter()
+            .all(|part| part.total == total && part.crc == crc),
+        PngErr::InvalidPart,
+    )?;
+
+    for (expected, part) in (0..total).zip(&parts) {
+        PngErr::not_or(part.sequence < expected, PngErr::InvalidPart)?;
+        PngErr::not_or(part.sequence > expected, PngErr::MissingPart)?;
+    }
+
+    let total = convert!(usize; total)?;
+    PngErr::not_or(parts.len() < total, PngErr::MissingPart)?;
+    PngErr::not_or(parts.len() > total, PngErr::InvalidPart)?;
+
+    let message: Vec<u8> = parts
+        .iter()
+        .flat_map(|part| part.payload)
+        .copied()
+        .collect();
+    PngErr::is_or(
+        crc::crc32::checksum_ieee(&message) == crc,
+        PngErr::InvalidPart,
+    )?;
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(message: &[u8], size: usize) -> Vec<Vec<u8>> {
+        split(message, size).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let message: Vec<u8> = (0..100).collect();
+        let parts = parts(&message, HEADER + 30);
+
+        assert_eq!(parts.len(), 4);
+        assert!(parts.iter().all(|part| part.len() <= HEADER + 30));
+        assert!(parts.iter().all(|part| is_part(part)));
+        assert_eq!(join(parts.iter().map(Vec::as_slice)).unwrap(), message);
+        assert_eq!(
+            join(parts.iter().rev().map(Vec::as_slice)).unwrap(),
+            message
+        );
+
+        let empty = split(b"", 100).unwrap();
+        assert_eq!(empty.len(), 1);
+        assert_eq!(join(empty.iter().map(Vec::as_slice)).unwrap(), b"");
+
+        assert!(!is_part(b"plain text"));
+        assert_eq!(split(b"message", HEADER), Err(PngErr::SplitTooSmall));
+        assert!(split(b"message", HEADER + 1).is_ok());
+    }
+
+    #[test]
+    fn test_missing_and_corrupt_parts() {
+        let parts = parts(&[7; 50], HEADER + 10);
+        let join_without = |skip: usize| {
+            join(
+                parts
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| *index != skip)
+                    .map(|(_, part)| part.as_slice()),
+            )
+        };
+
+        assert_eq!(join_without(0), Err(PngErr::MissingPart));
+        assert_eq!(join_without(2), Err(PngErr::MissingPart));
+        assert_eq!(join_without(4), Err(PngErr::MissingPart));
+        assert_eq!(join(None), Err(PngErr::MissingPart));
+
+        let twice = parts.iter().chain(&parts[1..2]).map(Vec::as_slice);
+        assert_eq!(join(twice), Err(PngErr::InvalidPart));
+
+        let mut corrupt = parts.clone();
+        corrupt[3][HEADER] ^= 1;
+        assert_eq!(
+            join(corrupt.iter().map(Vec::as_slice)),
+            Err(PngErr::InvalidPart)
+        );
+
+        let other = split(&[8; 50], HEADER + 10).unwrap();
+        let mixed = parts[..2].iter().chain(&other[2..]).map(Vec::as_slice);
+        assert_eq!(join(mixed), Err(PngErr::InvalidPart));
+    }
+}
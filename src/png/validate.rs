@@ -0,0 +1,553 @@
+use super::{Animation, Chunk, ChunkType, ColorType, Header, Ihdr, Png};
+use crate::{convert, err::*, INT_MAX};
+use std::{collections::HashMap, fmt};
+
+/// Chunks which may appear at most once.
+const UNIQUE: [&str; 14] = [
+    "IHDR", "PLTE", "IEND", "cHRM", "gAMA", "iCCP", "sBIT", "sRGB", "bKGD", "hIST", "tRNS", "pHYs",
+    "tIME", "acTL",
+];
+
+/// Chunks which must come before PLTE and the image data.
+const BEFORE_PLTE: [&str; 5] = ["cHRM", "gAMA", "iCCP", "sBIT", "sRGB"];
+
+/// Chunks which must come after PLTE, when there is one, but before the
+/// image data.
+const AFTER_PLTE: [&str; 3] = ["bKGD", "hIST", "tRNS"];
+
+/// Other chunks which must come before the image data.
+const BEFORE_IDAT: [&str; 3] = ["pHYs", "sPLT", "acTL"];
+
+/// Critical chunks this crate understands.
+const CRITICAL: [&str; 4] = ["IHDR", "PLTE", "IDAT", "IEND"];
+
+/// Chunks whose data has a fixed length.
+const LENGTHS: [(&str, usize); 7] = [
+    ("IEND", 0),
+    ("gAMA", 4),
+    ("cHRM", 32),
+    ("sRGB", 1),
+    ("pHYs", 9),
+    ("tIME", 7),
+    ("acTL", 8),
+];
+
+/// One way in which a file breaks the PNG specification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    BadSignature,
+    /// The file ends part way through a chunk.
+    Truncated,
+    BadChunkType,
+    BadLength,
+    BadCrc,
+    /// Bytes passed over to reach the next readable chunk.
+    Skipped(usize),
+    MissingIhdr,
+    IhdrNotFirst,
+    InvalidIhdr(PngErr),
+    Duplicate(String),
+    UnknownCritical(String),
+    WrongLength(String),
+    MissingPlte,
+    UnexpectedPlte,
+    InvalidPlte,
+    MissingIdat,
+    IdatNotContiguous,
+    MustPrecede(String, &'static str),
+    MustFollow(String, &'static str),
+    UnexpectedTrns,
+    IccpWithSrgb,
+    ImageData(PngErr),
+    Animation(PngErr),
+    MissingIend,
+    AfterIend,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::BadSignature => write!(f, "the PNG signature is missing or wrong"),
+            Problem::Truncated => write!(f, "the file ends part way through a chunk"),
+            Problem::BadChunkType => write!(f, "the chunk type is not four ASCII letters"),
+            Problem::BadLength => write!(f, "the chunk length exceeds 2^31 - 1"),
+            Problem::BadCrc => write!(f, "the CRC does not match the chunk contents"),
+            Problem::Skipped(length) => write!(f, "{} unreadable bytes were skipped", length),
+            Problem::MissingIhdr => write!(f, "there is no IHDR chunk"),
+            Problem::IhdrNotFirst => write!(f, "IHDR must be the first chunk"),
+            Problem::InvalidIhdr(err) => write!(f, "IHDR is invalid: {}", err),
+            Problem::Duplicate(name) => write!(f, "{} may only appear once", name),
+            Problem::UnknownCritical(name) => write!(f, "{} is an unknown critical chunk", name),
+            Problem::WrongLength(name) => write!(f, "{} has the wrong length", name),
+            Problem::MissingPlte => write!(f, "indexed images need a PLTE chunk"),
+            Problem::UnexpectedPlte => write!(f, "grayscale images may not have PLTE"),
+            Problem::InvalidPlte => write!(f, "PLTE has too many or a partial entry"),
+            Problem::MissingIdat => write!(f, "there is no IDAT chunk"),
+            Problem::IdatNotContiguous => write!(f, "IDAT chunks must be consecutive"),
+            Problem::MustPrecede(name, other) => write!(f, "{} must come before {}", name, other),
+            Problem::MustFollow(name, other) => write!(f, "{} must come after {}", name, other),
+            Problem::UnexpectedTrns => write!(f, "tRNS is not allowed with an alpha channel"),
+            Problem::IccpWithSrgb => write!(f, "iCCP and sRGB should not both be present"),
+            Problem::ImageData(err) => write!(f, "the image data is invalid: {}", err),
+            Problem::Animation(err) => write!(f, "the animation is invalid: {}", err),
+            Problem::MissingIend => write!(f, "there is no IEND chunk"),
+            Problem::AfterIend => write!(f, "chunks may not follow IEND"),
+        }
+    }
+}
+
+impl Problem {
+    /// Whether this only goes against a recommendation of the
+    /// specification, so doesn't make the file non-conformant.
+    pub fn is_warning(&self) -> bool {
+        matches!(self, Problem::IccpWithSrgb)
+    }
+}
+
+/// A problem found by `validate`, with the index of the chunk it concerns
+/// (if any) and the byte offset of that chunk in the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub index: Option<usize>,
+    pub offset: usize,
+    pub problem: Problem,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.index {
+            Some(index) => write!(
+                f,
+                "chunk {} at byte {}: {}",
+                index, self.offset, self.problem
+            ),
+            None => write!(f, "byte {}: {}", self.offset, self.problem),
+        }
+    }
+}
+
+/// Check `bytes` against the PNG specification, returning every violation
+/// found in file order. Unlike `Png::try_from`, reading carries on past bad
+/// CRCs so that later problems are reported too.
+pub fn validate(bytes: &[u8]) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let mut report = |index: Option<usize>, offset: usize, problem: Problem| {
+        violations.push(Violation {
+            index,
+            offset,
+            problem,
+        })
+    };
+
+    if bytes.get(..8) != Some(&Png::STANDARD_HEADER[..]) {
+        report(None, 0, Problem::BadSignature);
+        return violations;
+    }
+
+    let (chunks, places) = read_chunks(bytes, &mut report);
+    check_chunks(&chunks, &places, bytes.len(), &mut report);
+    violations.sort_by_key(|violation| violation.offset);
+    violations
+}
+
+/// Split the file into chunks, reporting those which can't be read. Each
+/// chunk is returned with its index in the file and byte offset, which
+/// count any unreadable chunks skipped before it.
+fn read_chunks(
+    bytes: &[u8],
+    report: &mut impl FnMut(Option<usize>, usize, Problem),
+) -> (Vec<Chunk>, Vec<(usize, usize)>) {
+    let (mut chunks, mut places) = (Vec::new(), Vec::new());
+    let mut offset = 8;
+
+    for count in 0.. {
+        if offset >= bytes.len() {
+            break;
+        }
+
+        let index = Some(count);
+        let Some(head) = bytes.get(offset..offset + 8) else {
+            report(index, offset, Problem::Truncated);
+            break;
+        };
+
+        let length = u32::from_be_bytes([head[0], head[1], head[2], head[3]]);
+        if length >= INT_MAX {
+            report(index, offset, Problem::BadLength);
+            break;
+        }
+
+        let end = offset + 12 + convert!(ex usize; length);
+        let Some(chunk) = bytes.get(offset..end) else {
+            report(index, offset, Problem::Truncated);
+            break;
+        };
+
+        let Ok(chunk_type) = ChunkType::from_bytes(&head[4..8]) else {
+            report(index, offset, Problem::BadChunkType);
+            offset = end;
+            continue;
+        };
+
+        let (body, crc) = chunk.split_at(chunk.len() - 4);
+        let chunk = Chunk::new(chunk_type, body[8..].to_vec());
+        if chunk.crc().to_be_bytes() != crc {
+            report(index, offset, Problem::BadCrc);
+        }
+
+        if !chunk_type.is_reserved_bit_valid() {
+            report(index, offset, Problem::BadChunkType);
+        }
+
+        chunks.push(chunk);
+        places.push((count, offset));
+        offset = end;
+    }
+
+    (chunks, places)
+}
+
+/// Check the order, count and contents of the chunks.
+fn check_chunks(
+    chunks: &[Chunk],
+    places: &[(usize, usize)],
+    len: usize,
+    report: &mut impl FnMut(Option<usize>, usize, Problem),
+) {
+    let names: Vec<String> = chunks
+        .iter()
+        .map(|chunk| chunk.chunk_type().to_string())
+        .collect();
+    let position = |name: &str| names.iter().position(|other| other == name);
+    let at = |i: usize| (Some(places[i].0), places[i].1);
+
+    let ihdr = match position("IHDR") {
+        None => {
+            report(None, 8, Problem::MissingIhdr);
+            None
+        }
+        Some(i) => {
+            let (index, offset) = at(i);
+            if i != 0 {
+                report(index, offset, Problem::IhdrNotFirst);
+            }
+
+            match Ihdr::try_from(&chunks[i]) {
+                Ok(ihdr) => Some((i, ihdr)),
+                Err(err) => {
+                    report(index, offset, Problem::InvalidIhdr(err));
+                    None
+                }
+            }
+        }
+    };
+
+    let plte = position("PLTE");
+    let first_idat = position("IDAT");
+    let iend = position("IEND");
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+
+    for (i, (name, chunk)) in names.iter().zip(chunks).enumerate() {
+        let (index, offset) = at(i);
+        let name = name.as_str();
+        let mut problem = |problem| report(index, offset, problem);
+
+        let count = seen.entry(name).or_default();
+        *count += 1;
+        if *count == 2 && UNIQUE.contains(&name) {
+            problem(Problem::Duplicate(name.to_string()));
+        }
+
+        if chunk.chunk_type().is_critical() && !CRITICAL.contains(&name) {
+            problem(Problem::UnknownCritical(name.to_string()));
+        }
+
+        if let Some((_, expected)) = LENGTHS.iter().find(|(other, _)| *other == name) {
+            if chunk.data().len() != *expected {
+                problem(Problem::WrongLength(name.to_string()));
+            }
+        }
+
+        if BEFORE_PLTE.contains(&name) && plte.is_some_and(|plte| i > plte) {
+            problem(Problem::MustPrecede(name.to_string(), "PLTE"));
+        }
+
+        if AFTER_PLTE.contains(&name) && plte.is_some_and(|plte| i < plte) {
+            problem(Problem::MustFollow(name.to_string(), "PLTE"));
+        }
+
+        let before_idat = ["PLTE"]
+            .iter()
+            .chain(&BEFORE_PLTE)
+            .chain(&AFTER_PLTE)
+            .chain(&BEFORE_IDAT)
+            .any(|&other| other == name);
+        if before_idat && first_idat.is_some_and(|idat| i > idat) {
+            problem(Problem::MustPrecede(name.to_string(), "IDAT"));
+        }
+
+        if name == "IDAT" && first_idat.is_some_and(|idat| idat < i) && names[i - 1] != "IDAT" {
+            problem(Problem::IdatNotContiguous);
+        }
+
+        if iend.is_some_and(|iend| i > iend) {
+            problem(Problem::AfterIend);
+        }
+    }
+
+    if seen.contains_key("iCCP") && seen.contains_key("sRGB") {
+        let (index, offset) = at(position("sRGB").unwrap_or(0));
+        report(index, offset, Problem::IccpWithSrgb);
+    }
+
+    let palette_ok = match ihdr {
+        Some((i, ihdr)) => check_palette(ihdr, i, chunks, at, report),
+        None => false,
+    };
+
+    match first_idat {
+        None => report(None, len, Problem::MissingIdat),
+        // Problems with the header were reported above, and would stop
+        // the image data being read.
+        Some(idat) if palette_ok => {
+            if let Err(err) = check_image(chunks) {
+                let (index, offset) = at(idat);
+                report(index, offset, Problem::ImageData(err));
+            }
+        }
+        _ => (),
+    }
+
+    if let Some(actl) = position("acTL") {
+        if let Err(err) = Animation::<u32>::from_png(&Png::from_chunks(chunks.to_vec())) {
+            let (index, offset) = at(actl);
+            report(index, offset, Problem::Animation(err));
+        }
+    }
+
+    if iend.is_none() {
+        report(None, len, Problem::MissingIend);
+    }
+}
+
+/// Check PLTE and tRNS against the color type, reporting a missing PLTE
+/// against the IHDR at `ihdr_index`. Returns whether the image data can
+/// still be read.
+fn check_palette(
+    ihdr: Ihdr,
+    ihdr_index: usize,
+    chunks: &[Chunk],
+    at: impl Fn(usize) -> (Option<usize>, usize),
+    report: &mut impl FnMut(Option<usize>, usize, Problem),
+) -> bool {
+    let find = |name: &str| {
+        chunks
+            .iter()
+            .position(|chunk| chunk.chunk_type().to_string() == name)
+    };
+    let mut report_at = |index: usize, problem| {
+        let (index, offset) = at(index);
+        report(index, offset, problem);
+    };
+
+    let readable = match (ihdr.color_type, find("PLTE")) {
+        (ColorType::Indexed, None) => {
+            report_at(ihdr_index, Problem::MissingPlte);
+            false
+        }
+        (ColorType::Grayscale | ColorType::GrayscaleAlpha, Some(plte)) => {
+            report_at(plte, Problem::UnexpectedPlte);
+            true
+        }
+        (color_type, Some(plte)) => {
+            let len = chunks[plte].data().len();
+            let max = match color_type {
+                ColorType::Indexed => 1 << ihdr.bit_depth,
+                _ => 256,
+            };
+
+            let valid = len > 0 && len.is_multiple_of(3) && len / 3 <= max;
+            if !valid {
+                report_at(plte, Problem::InvalidPlte);
+            }
+            valid || color_type != ColorType::Indexed
+        }
+        _ => true,
+    };
+
+    if let Some(trns) = find("tRNS") {
+        if matches!(ihdr.color_type, ColorType::GrayscaleAlpha | ColorType::Rgba) {
+            report_at(trns, Problem::UnexpectedTrns);
+        }
+    }
+
+    readable
+}
+
+/// Decompress and unfilter the image data. The color space chunks are
+/// left out, since they don't affect reading it and are checked already.
+fn check_image(chunks: &[Chunk]) -> PngRes {
+    let layout: Vec<Chunk> = chunks
+        .iter()
+        .filter(|chunk| ["IHDR", "PLTE", "tRNS"].contains(&chunk.chunk_type().to_string().as_str()))
+        .cloned()
+        .collect();
+
+    let header = Header::from_chunks(&layout)?;
+    let idat: Vec<u8> = chunks
+        .iter()
+        .filter(|chunk| chunk.chunk_type().to_string() == "IDAT")
+        .flat_map(|chunk| chunk.data().iter().copied())
+        .collect();
+
+    header.pixels::<u32>(&idat, header.ihdr.width, header.ihdr.height)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{img::Img, png::EncodeOptions};
+    use std::str::FromStr;
+
+    fn chunk(name: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(name).unwrap(), data.to_vec())
+    }
+
+    fn valid() -> Vec<Chunk> {
+        let img: Img = Img::new_bg(4, 4, 0x336699FF);
+        let options = EncodeOptions::new().reduce(false).idat_size(20);
+        Png::from_img_with(img, &options).unwrap().chunks().to_vec()
+    }
+
+    fn problems(chunks: Vec<Chunk>) -> Vec<(Option<usize>, Problem)> {
+        validate(&Png::from_chunks(chunks).as_bytes())
+            .into_iter()
+            .map(|violation| (violation.index, violation.problem))
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_file() {
+        assert!(valid().len() > 3);
+        assert_eq!(problems(valid()), []);
+    }
+
+    #[test]
+    fn test_structure() {
+        let mut chunks = valid();
+        let ihdr = chunks.remove(0);
+        chunks.insert(1, ihdr.clone());
+        chunks.insert(2, ihdr);
+        chunks.pop();
+
+        assert_eq!(
+            problems(chunks),
+            [
+                (Some(1), Problem::IhdrNotFirst),
+                (Some(2), Problem::Duplicate("IHDR".into())),
+                (Some(3), Problem::IdatNotContiguous),
+                (None, Problem::MissingIend),
+            ]
+        );
+
+        let mut chunks = valid();
+        chunks.retain(|chunk| chunk.chunk_type().to_string() != "IDAT");
+        chunks.push(chunk("tEXt", b"late"));
+        assert_eq!(
+            problems(chunks),
+            [(Some(2), Problem::AfterIend), (None, Problem::MissingIdat)]
+        );
+    }
+
+    #[test]
+    fn test_ordering() {
+        let mut chunks = valid();
+        let last = chunks.len() - 1;
+        chunks.insert(last - 1, chunk("tEXt", b"between"));
+        chunks.insert(last, chunk("gAMA", &[0, 0, 0xB1, 0x8F]));
+        chunks.insert(1, chunk("PLTE", &[0, 0, 0]));
+        chunks.insert(1, chunk("bKGD", &[0; 6]));
+
+        let found = problems(chunks);
+        assert!(found.contains(&(Some(1), Problem::MustFollow("bKGD".into(), "PLTE"))));
+        assert!(found.contains(&(Some(6), Problem::IdatNotContiguous)));
+        assert!(found.contains(&(Some(5), Problem::MustPrecede("gAMA".into(), "PLTE"))));
+        assert!(found.contains(&(Some(5), Problem::MustPrecede("gAMA".into(), "IDAT"))));
+    }
+
+    #[test]
+    fn test_contents() {
+        let mut chunks = valid();
+        chunks[0] = Chunk::new(
+            ChunkType::from_str("IHDR").unwrap(),
+            [&[0, 0, 0, 4, 0, 0, 0, 4][..], &[3, 2, 0, 0, 0]].concat(),
+        );
+        assert_eq!(
+            problems(chunks)[0],
+            (Some(0), Problem::InvalidIhdr(PngErr::InvalidBitDepth))
+        );
+
+        let mut chunks = valid();
+        chunks[0] = chunk("IHDR", &[0, 0, 0, 4, 0, 0, 0, 4, 8, 3, 0, 0, 0]);
+        assert_eq!(problems(chunks), [(Some(0), Problem::MissingPlte)]);
+
+        let mut chunks = valid();
+        chunks.insert(1, chunk("tRNS", &[0, 0]));
+        chunks.insert(1, chunk("ZZZZ", b""));
+        chunks.insert(1, chunk("sRGB", &[0, 0]));
+        assert_eq!(
+            problems(chunks),
+            [
+                (Some(1), Problem::WrongLength("sRGB".into())),
+                (Some(2), Problem::UnknownCritical("ZZZZ".into())),
+                (Some(3), Problem::UnexpectedTrns),
+            ]
+        );
+
+        let mut chunks = valid();
+        chunks.retain(|chunk| chunk.chunk_type().to_string() != "IDAT");
+        chunks.insert(1, chunk("IDAT", &[0x78, 0x9C, 3, 0]));
+        assert!(matches!(
+            problems(chunks)[..],
+            [(Some(1), Problem::ImageData(_))]
+        ));
+
+        let mut chunks = valid();
+        chunks.insert(1, chunk("sRGB", &[0]));
+        chunks.insert(
+            1,
+            chunk("iCCP", b"profile\0\0\x78\x9C\x03\x00\x00\x00\x00\x01"),
+        );
+        let found = problems(chunks);
+        assert_eq!(found, [(Some(2), Problem::IccpWithSrgb)]);
+        assert!(found.iter().all(|(_, problem)| problem.is_warning()));
+        assert!(!Problem::MissingIend.is_warning());
+    }
+
+    #[test]
+    fn test_offsets_and_damage() {
+        let mut bytes = Png::from_chunks(valid()).as_bytes();
+        bytes[20] ^= 1;
+        bytes.truncate(bytes.len() - 2);
+
+        let violations = validate(&bytes);
+        assert_eq!(
+            violations[0].to_string(),
+            format!("chunk 0 at byte 8: {}", Problem::BadCrc)
+        );
+        assert_eq!(violations.last().unwrap().problem, Problem::MissingIend);
+        assert!(violations
+            .iter()
+            .any(|violation| violation.problem == Problem::Truncated));
+
+        assert_eq!(
+            validate(b"GIF89a"),
+            [Violation {
+                index: None,
+                offset: 0,
+                problem: Problem::BadSignature
+            }]
+        );
+    }
+}
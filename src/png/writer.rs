@@ -0,0 +1,390 @@
+use super::{
+    adam7, encode::RowEncoder, AsChunkRef, Chunk, EncodeOptions, Ihdr, PixelFormat, Png, ZlibWriter,
+};
+use crate::{convert, err::*, img::Img, Color, Quad, INT_MAX};
+use std::io::{self, Write};
+
+/// Somewhere complete chunks can be sent, either an output stream or a
+/// `Png` being built in memory.
+pub trait ChunkSink {
+    fn write_chunk(&mut self, chunk: Chunk) -> PngRes;
+}
+
+impl ChunkSink for Png {
+    fn write_chunk(&mut self, chunk: Chunk) -> PngRes {
+        self.push_chunk(chunk);
+        Ok(())
+    }
+}
+
+/// Writes chunks to any `Write` as soon as they are given, without
+/// keeping them.
+#[derive(Debug)]
+pub struct ChunkWriter<W> {
+    inner: W,
+}
+
+impl<W: Write> ChunkWriter<W> {
+    /// Write bare chunks, with no PNG signature.
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Start a PNG stream by writing the signature.
+    pub fn png(mut inner: W) -> PngRes<Self> {
+        inner
+            .write_all(&Png::STANDARD_HEADER)
+            .map_err(|_| PngErr::WriteError)?;
+
+        Ok(Self::new(inner))
+    }
+
+    pub fn write(&mut self, chunk: &impl AsChunkRef) -> PngRes {
+        chunk.as_chunk_ref().write_to(&mut self.inner)
+    }
+
+    /// Flush and return the underlying writer.
+    pub fn finish(mut self) -> PngRes<W> {
+        self.inner.flush().map_err(|_| PngErr::WriteError)?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> ChunkSink for ChunkWriter<W> {
+    fn write_chunk(&mut self, chunk: Chunk) -> PngRes {
+        self.write(&chunk)
+    }
+}
+
+/// Encodes an image row by row, compressing each row as it arrives and
+/// sending the image data on in IDAT chunks of at most
+/// `EncodeOptions::idat_size` bytes. Neither the scanlines nor the
+/// compressed data are ever held in full.
+pub struct ImageWriter<S: ChunkSink> {
+    ihdr: Ihdr,
+    options: EncodeOptions,
+    rows: RowEncoder,
+    written: u32,
+    encoder: ZlibWriter<IdatWriter<S>>,
+}
+
+impl<S: ChunkSink> ImageWriter<S> {
+    /// Scanline bytes given to each thread by `write_bands`.
+    const BAND: usize = 131072;
+
+    /// Write IHDR, then `chunks`, then the chunks needed by `format`, and
+    /// prepare to receive `height` rows of `width` pixels.
+    pub fn new(
+        mut sink: S,
+        width: u32,
+        height: u32,
+        format: &PixelFormat,
+        options: &EncodeOptions,
+        chunks: Vec<Chunk>,
+    ) -> PngRes<Self> {
+        PngErr::is_or(
+            (1..=convert!(usize; INT_MAX - 1)?).contains(&options.idat_size),
+            PngErr::InvalidOption,
+        )?;
+        options.deflate.check()?;
+
+        let ihdr = Ihdr {
+            interlace: u8::from(options.interlace),
+            ..format.ihdr(width, height)
+        };
+
+        sink.write_chunk(ihdr.to_chunk()?)?;
+        for chunk in chunks.into_iter().chain(format.chunks()?) {
+            sink.write_chunk(chunk)?;
+        }
+
+        Ok(Self {
+            ihdr,
+            options: options.clone(),
+            rows: RowEncoder::new(format, width),
+            written: 0,
+            encoder: ZlibWriter::new(IdatWriter::new(sink, options.idat_size), &options.deflate)?,
+        })
+    }
+
+    /// Write the next row. `filter` is the filter type to use when the
+    /// strategy is `Preserve`. Interlaced images must be written with
+    /// `write_img` instead, since their rows are not stored in order.
+    pub fn write_row<P: Color>(&mut self, row: &[P], filter: u8) -> PngRes {
+        PngErr::not_or(self.ihdr.interlace == 1, PngErr::InvalidOption)?;
+        PngErr::not_or(self.written >= self.ihdr.height, PngErr::DataLengthMismatch)?;
+        PngErr::is_or(
+            row.len() == convert!(usize; self.ihdr.width)?,
+            PngErr::DataLengthMismatch,
+        )?;
+
+        let scanline = self.rows.encode(row, filter, self.options.filter);
+        self.encoder
+            .write_all(scanline)
+            .map_err(|_| PngErr::WriteError)?;
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Write every row of `img`, which must match the header dimensions.
+    pub fn write_img<P: Color>(&mut self, img: &Img<P>) -> PngRes {
+        PngErr::is_or(
+            img.dimensions() == (self.ihdr.width, self.ihdr.height) && self.written == 0,
+            PngErr::DataLengthMismatch,
+        )?;
+
+        let threads = match self.options.threads {
+            0 => std::thread::available_parallelism().map_or(1, usize::from),
+            threads => threads,
+        };
+
+        if self.ihdr.interlace == 0 && threads > 1 {
+            return self.write_bands(img, threads);
+        }
+
+        if self.ihdr.interlace == 0 {
+            let width = convert!(usize; img.width())?;
+            for (row, &filter) in img.data().chunks(width).zip(img.filters()) {
+                self.write_row(row, filter)?;
+            }
+
+            return Ok(());
+        }
+
+        let format = self.rows.format().clone();
+        for pass in adam7::passes(img.width(), img.height()).filter(|pass| !pass.is_empty()) {
+            let pixels = pass.extract(img.data(), img.width());
+            let mut rows = RowEncoder::new(&format, pass.width);
+
+            for row in pixels.chunks(convert!(usize; pass.width)?) {
+                let scanline = rows.encode(row, 0, self.options.filter);
+                self.encoder
+                    .write_all(scanline)
+                    .map_err(|_| PngErr::WriteError)?;
+            }
+        }
+
+        self.written = self.ihdr.height;
+        Ok(())
+    }
+
+    /// Filter and compress bands of about `Self::BAND` bytes of scanlines
+    /// on separate threads. Each band is seeded with the row above it, so
+    /// the filtered data is exactly what `write_row` would produce.
+    fn write_bands<P: Color>(&mut self, img: &Img<P>, threads: usize) -> PngRes {
+        let width = convert!(usize; img.width())?;
+        let stride = self.ihdr.stride(img.width())? + 1;
+        let band = (Self::BAND / stride).max(1);
+        let rows: Vec<&[P]> = img.data().chunks(width).collect();
+        let count = rows.len().div_ceil(band);
+
+        let (format, strategy) = (self.rows.format(), self.options.filter);
+        self.encoder.write_segments(count, threads, |index| {
+            let first = index * band;
+            let mut encoder = RowEncoder::new(format, img.width());
+            if first > 0 {
+                encoder.seed(rows[first - 1]);
+            }
+
+            let mut data = Vec::with_capacity(band * stride);
+            for (row, &filter) in rows.iter().zip(img.filters()).skip(first).take(band) {
+                data.extend_from_slice(encoder.encode(row, filter, strategy));
+            }
+
+            Ok(data)
+        })?;
+
+        self.written = self.ihdr.height;
+        Ok(())
+    }
+
+    /// Flush the remaining image data and write IEND, returning the sink.
+    pub fn finish(self) -> PngRes<S> {
+        PngErr::is_or(self.written == self.ihdr.height, PngErr::DataLengthMismatch)?;
+
+        let idat = self.encoder.finish()?;
+        let mut sink = idat.finish()?;
+        sink.write_chunk(Chunk::iend()?)?;
+        Ok(sink)
+    }
+}
+
+/// Collects compressed image data and passes it on in IDAT chunks.
+struct IdatWriter<S: ChunkSink> {
+    sink: S,
+    buf: Vec<u8>,
+    size: usize,
+}
+
+impl<S: ChunkSink> IdatWriter<S> {
+    fn new(sink: S, size: usize) -> Self {
+        Self {
+            sink,
+            buf: Vec::with_capacity(size.min(Png::CHUNK_SIZE)),
+            size,
+        }
+    }
+
+    fn emit(&mut self, len: usize) -> PngRes {
+        let chunk = Chunk::idat(&self.buf[..len])?;
+        self.buf.drain(..len);
+        self.sink.write_chunk(chunk)
+    }
+
+    fn finish(mut self) -> PngRes<S> {
+        if !self.buf.is_empty() {
+            self.emit(self.buf.len())?;
+        }
+
+        Ok(self.sink)
+    }
+}
+
+impl<S: ChunkSink> Write for IdatWriter<S> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        while self.buf.len() >= self.size {
+            self.emit(self.size).map_err(io::Error::other)?;
+        }
+
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::ChunkReader;
+
+    fn gradient(width: u32, height: u32) -> Img {
+        let data = (0..width * height).map(|px| px.wrapping_mul(0x9E3779B9) | 0xFF);
+        Img::from_vec(width, height, data.collect())
+    }
+
+    #[test]
+    fn test_rows_are_streamed() {
+        let img = gradient(300, 200);
+        let format = PixelFormat::rgba::<u32>();
+        let options = EncodeOptions::new();
+
+        let stream = ChunkWriter::png(Vec::new()).unwrap();
+        let mut writer = ImageWriter::new(stream, 300, 200, &format, &options, vec![]).unwrap();
+        for row in img.data().chunks(300) {
+            writer.write_row(row, 0).unwrap();
+        }
+
+        let bytes = writer.finish().unwrap().finish().unwrap();
+        let idat: Vec<usize> = ChunkReader::png(&bytes[..])
+            .unwrap()
+            .map(Result::unwrap)
+            .filter(|chunk| chunk.chunk_type().to_string() == "IDAT")
+            .map(|chunk| chunk.data().len())
+            .collect();
+
+        assert!(idat.len() > 1);
+        assert!(idat[..idat.len() - 1]
+            .iter()
+            .all(|&len| len == Png::CHUNK_SIZE));
+        assert_eq!(
+            Png::from_reader(&bytes[..])
+                .unwrap()
+                .to_img()
+                .unwrap()
+                .data(),
+            img.data()
+        );
+    }
+
+    #[test]
+    fn test_row_count_is_checked() {
+        let format = PixelFormat::rgba::<u32>();
+        let options = EncodeOptions::new();
+        let row = [0u32; 4];
+
+        let mut writer = ImageWriter::new(Png::new(), 4, 2, &format, &options, vec![]).unwrap();
+        writer.write_row(&row, 0).unwrap();
+        assert!(writer.write_row(&row[..3], 0).is_err());
+
+        let mut writer = ImageWriter::new(Png::new(), 4, 2, &format, &options, vec![]).unwrap();
+        writer.write_row(&row, 0).unwrap();
+        assert!(ImageWriter::finish(writer).is_err());
+
+        let interlaced = options.interlace(true);
+        let mut writer = ImageWriter::new(Png::new(), 4, 2, &format, &interlaced, vec![]).unwrap();
+        assert!(writer.write_row(&row, 0).is_err());
+    }
+
+    #[test]
+    fn test_compression_options() {
+        let img = gradient(120, 80);
+        let format = PixelFormat::rgba::<u32>();
+        let idat = |png: &Png| {
+            png.chunks()
+                .iter()
+                .filter(|chunk| chunk.chunk_type().to_string() == "IDAT")
+                .map(|chunk| chunk.data().len())
+                .collect::<Vec<_>>()
+        };
+
+        let options = EncodeOptions::new().level(9).window(10).idat_size(1000);
+        let png = Png::from_img_with(img.clone(), &options).unwrap();
+        let sizes = idat(&png);
+        assert!(sizes.len() > 2);
+        assert!(sizes[..sizes.len() - 1].iter().all(|&len| len == 1000));
+        assert_eq!(png.chunk_by_type("IDAT").unwrap().data()[0], 0x28);
+        assert_eq!(png.to_img().unwrap().data(), img.data());
+
+        let stored = EncodeOptions::new().level(0).reduce(false);
+        let png = Png::from_img_with(img.clone(), &stored).unwrap();
+        assert!(idat(&png).iter().sum::<usize>() > 120 * 80 * 4);
+
+        for options in [
+            EncodeOptions::new().idat_size(0),
+            EncodeOptions::new().level(10),
+            EncodeOptions::new().window(16),
+        ] {
+            assert!(ImageWriter::new(Png::new(), 1, 1, &format, &options, vec![]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_threads_match_serial_scanlines() {
+        let img = gradient(700, 300);
+        let scanlines = |options: &EncodeOptions| {
+            let png = Png::from_img_with(img.clone(), options).unwrap();
+            let idat: Vec<u8> = png
+                .chunks()
+                .iter()
+                .filter(|chunk| chunk.chunk_type().to_string() == "IDAT")
+                .flat_map(|chunk| chunk.data().to_vec())
+                .collect();
+
+            assert_eq!(png.to_img().unwrap().data(), img.data());
+            crate::fs::decompress(&idat).unwrap()
+        };
+
+        let serial = scanlines(&EncodeOptions::new());
+        for threads in [0, 2, 5] {
+            let options = EncodeOptions::new().threads(threads);
+            assert_eq!(scanlines(&options), serial);
+            assert_eq!(scanlines(&options.window(9)), serial);
+        }
+    }
+
+    #[test]
+    fn test_write_png_matches_from_img() {
+        let img = gradient(40, 30);
+
+        for options in [EncodeOptions::new(), EncodeOptions::new().interlace(true)] {
+            let mut bytes = Vec::new();
+            Png::write_img(&img, &mut bytes, &options, vec![]).unwrap();
+
+            let png = Png::from_img_with(img.clone(), &options).unwrap();
+            assert_eq!(bytes, png.as_bytes());
+        }
+    }
+}
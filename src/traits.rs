@@ -1,13 +1,62 @@
 use crate::{area, calc, convert, err::*};
+use std::fmt::Debug;
+
+/// A packed RGBA pixel. `u32` holds 8 bits per channel and `u64` holds 16.
+pub trait Color: Sized + Copy + Default + PartialEq + Debug + Send + Sync {
+    /// Bits per channel.
+    const DEPTH: u8;
 
-pub trait Color: Sized {
     fn _color_value(&self) -> Self;
+
+    fn from_rgba16(rgba: [u16; 4]) -> Self;
+    fn to_rgba16(self) -> [u16; 4];
+
+    /// The nearest 8-bit color.
+    fn to_rgba8(self) -> u32 {
+        u32::from_rgba16(self.to_rgba16())
+    }
+
+    /// True if converting to 8-bit and back would not change the color.
+    fn fits_rgba8(self) -> bool {
+        self.to_rgba16().iter().all(|ch| ch >> 8 == ch & 0xFF)
+    }
 }
 
 impl Color for u32 {
+    const DEPTH: u8 = 8;
+
+    fn _color_value(&self) -> Self {
+        *self
+    }
+
+    fn from_rgba16(rgba: [u16; 4]) -> Self {
+        let narrow = |ch: u16| ((u32::from(ch) * 255 + 32767) / 65535) as u8;
+        u32::from_be_bytes(rgba.map(narrow))
+    }
+
+    fn to_rgba16(self) -> [u16; 4] {
+        self.to_be_bytes().map(|ch| u16::from(ch) * 257)
+    }
+
+    fn to_rgba8(self) -> u32 {
+        self
+    }
+}
+
+impl Color for u64 {
+    const DEPTH: u8 = 16;
+
     fn _color_value(&self) -> Self {
         *self
     }
+
+    fn from_rgba16([r, g, b, a]: [u16; 4]) -> Self {
+        u64::from(r) << 48 | u64::from(g) << 32 | u64::from(b) << 16 | u64::from(a)
+    }
+
+    fn to_rgba16(self) -> [u16; 4] {
+        [48, 32, 16, 0].map(|shift| (self >> shift) as u16)
+    }
 }
 
 pub trait Quad {
@@ -23,7 +72,27 @@ pub trait Quad {
     }
 }
 
-pub trait Image: Quad {
-    fn to_vec(self) -> Vec<u32>;
-    fn clone_to_vec(&self) -> Vec<u32>;
+pub trait Image<P: Color = u32>: Quad {
+    fn to_vec(self) -> Vec<P>;
+    fn clone_to_vec(&self) -> Vec<P>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_depths() {
+        let px = 0x12345678u32;
+        assert_eq!(px.to_rgba16(), [0x1212, 0x3434, 0x5656, 0x7878]);
+        assert_eq!(u64::from_rgba16(px.to_rgba16()), 0x1212343456567878);
+        assert_eq!(0x1212343456567878u64.to_rgba8(), px);
+        assert!(0x1212343456567878u64.fits_rgba8());
+
+        let deep = 0x123456789ABCDEF0u64;
+        assert_eq!(deep.to_rgba16(), [0x1234, 0x5678, 0x9ABC, 0xDEF0]);
+        assert_eq!(u64::from_rgba16(deep.to_rgba16()), deep);
+        assert_eq!(deep.to_rgba8(), 0x12569ADE);
+        assert!(!deep.fits_rgba8());
+    }
 }